@@ -0,0 +1,461 @@
+//! Caching layer in front of a [`ThreadSafeDNSResolver`], keyed by
+//! `(name, record type)` with a fixed positive-answer TTL (answers from
+//! [`ClashResolver`] carry no record TTL), backed by a
+//! ClockPro-style eviction policy for bounded memory with good scan
+//! resistance.
+//!
+//! ClockPro keeps hot and cold resident pages plus non-resident "test"
+//! entries on a single circular buffer swept by a clock hand:
+//! - a hit sets the entry's reference bit.
+//! - a miss inserts a new cold page.
+//! - when the cache is full, the hand sweeps forward: a cold page with its
+//!   reference bit set is promoted to hot; otherwise it is demoted to a
+//!   non-resident test entry. A hot page with its reference bit set keeps
+//!   hot status (bit cleared); otherwise it is demoted to cold.
+//! - a miss that lands on a non-resident test entry adaptively enlarges the
+//!   hot allocation, since it's evidence the working set needs more room.
+//! - expired entries (TTL reached) are always treated as misses, regardless
+//!   of what page state they were in.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    hash::Hash,
+    net::IpAddr,
+    sync::Arc,
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use tokio::{sync::Mutex, time::Instant};
+
+use crate::app::dns::{ClashResolver, ThreadSafeDNSResolver};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Status {
+    Hot,
+    Cold,
+    /// non-resident: key is remembered, but the value has been evicted.
+    Test,
+}
+
+struct Page<K, V> {
+    key: K,
+    value: Option<V>,
+    status: Status,
+    reference: bool,
+    expires_at: Option<Instant>,
+}
+
+/// A bounded cache with ClockPro-style eviction.
+///
+/// `capacity` bounds the number of resident (hot + cold) entries; the
+/// non-resident test directory is bounded to roughly the same size.
+pub struct ClockProCache<K, V> {
+    capacity: usize,
+    hot_target: usize,
+    hot_count: usize,
+    cold_count: usize,
+    /// non-resident test entries currently on the ring, oldest-demoted
+    /// first. Bounds the test directory to `capacity` so a sustained
+    /// stream of distinct, never-repeated keys can't grow `ring`/`index`
+    /// without limit.
+    test_order: VecDeque<K>,
+    test_count: usize,
+    hand: usize,
+    ring: Vec<Page<K, V>>,
+    index: HashMap<K, usize>,
+}
+
+/// Upper bound on `hot_target`: always leaves at least one resident slot
+/// cold/evictable, so `sweep`'s hot-page branch (`hot_count > hot_target`)
+/// can always eventually fire and terminate the sweep. At `capacity == 1`
+/// this is 0 - the sole page must itself be treated as cold-evictable,
+/// since there's no room to also dedicate a slot to a permanently-hot one.
+/// Without this, `capacity == 1` lets `hot_target` reach `capacity`, and
+/// once the sole resident page is promoted to hot, `hot_count > hot_target`
+/// is never true again - `sweep` (whose only `return` is in the
+/// cold-and-unreferenced branch) spins forever on every later `insert`.
+fn max_hot_target(capacity: usize) -> usize {
+    capacity.saturating_sub(1)
+}
+
+impl<K: Eq + Hash + Clone, V: Clone> ClockProCache<K, V> {
+    pub fn new(capacity: usize) -> Self {
+        assert!(capacity > 0, "cache capacity must be > 0");
+        Self {
+            capacity,
+            hot_target: (capacity / 2).min(max_hot_target(capacity)),
+            hot_count: 0,
+            cold_count: 0,
+            test_order: VecDeque::new(),
+            test_count: 0,
+            hand: 0,
+            ring: Vec::new(),
+            index: HashMap::new(),
+        }
+    }
+
+    /// Looks up `key`. Returns `None` on a miss, on a non-resident test
+    /// entry, or when the entry has expired (treated as a miss regardless
+    /// of page state).
+    pub fn get(&mut self, key: &K) -> Option<V> {
+        let pos = *self.index.get(key)?;
+
+        if matches!(self.ring[pos].expires_at, Some(t) if Instant::now() >= t) {
+            self.demote_to_test(pos);
+            return None;
+        }
+
+        let page = &mut self.ring[pos];
+        if page.status == Status::Test {
+            return None;
+        }
+
+        page.reference = true;
+        page.value.clone()
+    }
+
+    /// Whether `key` is currently a non-resident test entry, i.e. it was
+    /// resident recently enough to still be remembered. Callers use this on
+    /// a miss to decide whether to enlarge the hot allocation.
+    pub fn is_test_entry(&self, key: &K) -> bool {
+        self.index
+            .get(key)
+            .is_some_and(|&pos| self.ring[pos].status == Status::Test)
+    }
+
+    /// Inserts a freshly-resolved value as a cold page, expiring `ttl` from
+    /// now. If `key` was a test entry, the hot allocation is enlarged first
+    /// (the adaptive step that distinguishes ClockPro from plain CLOCK).
+    pub fn insert(&mut self, key: K, value: V, ttl: Duration) {
+        if self.is_test_entry(&key) {
+            self.hot_target = (self.hot_target + 1).min(max_hot_target(self.capacity));
+        }
+
+        let expires_at = Some(Instant::now() + ttl);
+
+        if let Some(&pos) = self.index.get(&key) {
+            let was_test = self.ring[pos].status == Status::Test;
+            self.ring[pos] = Page {
+                key: key.clone(),
+                value: Some(value),
+                status: Status::Cold,
+                reference: false,
+                expires_at,
+            };
+            if was_test {
+                self.cold_count += 1;
+                self.test_count = self.test_count.saturating_sub(1);
+            }
+            return;
+        }
+
+        while self.hot_count + self.cold_count >= self.capacity {
+            self.sweep();
+        }
+
+        let pos = self.ring.len();
+        self.ring.push(Page {
+            key: key.clone(),
+            value: Some(value),
+            status: Status::Cold,
+            reference: false,
+            expires_at,
+        });
+        self.index.insert(key, pos);
+        self.cold_count += 1;
+    }
+
+    fn demote_to_test(&mut self, pos: usize) {
+        match self.ring[pos].status {
+            Status::Hot => self.hot_count = self.hot_count.saturating_sub(1),
+            Status::Cold => self.cold_count = self.cold_count.saturating_sub(1),
+            Status::Test => return,
+        }
+        self.ring[pos].status = Status::Test;
+        self.ring[pos].value = None;
+        self.ring[pos].expires_at = None;
+        self.test_order.push_back(self.ring[pos].key.clone());
+        self.test_count += 1;
+        self.evict_test_overflow();
+    }
+
+    /// Drops the oldest non-resident test entries until the test directory
+    /// is back within `capacity`, so a sustained stream of distinct,
+    /// never-repeated keys (each demoted to a test entry exactly once and
+    /// never looked up again) can't grow `ring`/`index` without bound.
+    fn evict_test_overflow(&mut self) {
+        while self.test_count > self.capacity {
+            let Some(key) = self.test_order.pop_front() else {
+                break;
+            };
+            let Some(&pos) = self.index.get(&key) else {
+                continue;
+            };
+            if self.ring[pos].status != Status::Test {
+                // stale queue entry - the slot was reused by insert()
+                // before this eviction ever ran.
+                continue;
+            }
+            self.remove_ring_entry(pos);
+            self.test_count -= 1;
+        }
+    }
+
+    /// Removes the ring slot at `pos` entirely via swap-remove, fixing up
+    /// `index` for whatever entry lands there and wrapping the clock hand
+    /// if it pointed past the new end of the ring.
+    fn remove_ring_entry(&mut self, pos: usize) {
+        self.index.remove(&self.ring[pos].key);
+        let last = self.ring.len() - 1;
+        if pos != last {
+            self.ring.swap(pos, last);
+            self.index.insert(self.ring[pos].key.clone(), pos);
+        }
+        self.ring.pop();
+
+        if self.hand >= self.ring.len() {
+            self.hand = 0;
+        }
+    }
+
+    /// Advances the clock hand by one slot, promoting/demoting pages per
+    /// the reference-bit rule, until it frees up a resident slot.
+    fn sweep(&mut self) {
+        if self.ring.is_empty() {
+            return;
+        }
+
+        loop {
+            let pos = self.hand;
+            self.hand = (self.hand + 1) % self.ring.len();
+
+            match self.ring[pos].status {
+                Status::Cold => {
+                    if self.ring[pos].reference {
+                        self.ring[pos].status = Status::Hot;
+                        self.ring[pos].reference = false;
+                        self.cold_count -= 1;
+                        self.hot_count += 1;
+                    } else {
+                        self.demote_to_test(pos);
+                        return;
+                    }
+                }
+                Status::Hot => {
+                    if self.ring[pos].reference {
+                        self.ring[pos].reference = false;
+                    } else if self.hot_count > self.hot_target {
+                        self.ring[pos].status = Status::Cold;
+                        self.hot_count -= 1;
+                        self.cold_count += 1;
+                    }
+                }
+                Status::Test => {
+                    // already non-resident - hand just passes through.
+                }
+            }
+        }
+    }
+}
+
+/// Config for [`CachedResolver`].
+#[derive(Clone, Debug)]
+pub struct CacheConfig {
+    /// number of resident answers kept; bounds memory, not TTL.
+    pub capacity: usize,
+    /// how long a negative (not-found) answer is cached for, so a
+    /// consistently-failing name doesn't re-query upstream every request.
+    pub negative_ttl: Duration,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            capacity: 4096,
+            negative_ttl: Duration::from_secs(30),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+enum RecordType {
+    /// either family, as answered by `resolve`.
+    Any,
+    A,
+    Aaaa,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Answer {
+    Found(IpAddr),
+    NotFound,
+}
+
+/// Wraps a [`ThreadSafeDNSResolver`] with a ClockPro-evicted answer cache,
+/// keyed by `(name, record type)`. Positive answers are cached for a fixed
+/// 60 seconds (see [`ip_ttl`]); the record TTL of the upstream answer is not
+/// used. Negative answers are cached for [`CacheConfig::negative_ttl`].
+pub struct CachedResolver {
+    inner: ThreadSafeDNSResolver,
+    negative_ttl: Duration,
+    cache: Mutex<ClockProCache<(String, RecordType), Answer>>,
+}
+
+impl CachedResolver {
+    pub fn new(inner: ThreadSafeDNSResolver, cfg: CacheConfig) -> Arc<Self> {
+        Arc::new(Self {
+            cache: Mutex::new(ClockProCache::new(cfg.capacity)),
+            negative_ttl: cfg.negative_ttl,
+            inner,
+        })
+    }
+}
+
+#[async_trait]
+impl ClashResolver for CachedResolver {
+    async fn resolve(&self, host: &str, enhanced: bool) -> anyhow::Result<Option<IpAddr>> {
+        let key = (host.to_owned(), RecordType::Any);
+        if let Some(answer) = self.cache.lock().await.get(&key) {
+            return Ok(match answer {
+                Answer::Found(ip) => Some(ip),
+                Answer::NotFound => None,
+            });
+        }
+
+        let result = self.inner.resolve(host, enhanced).await?;
+        let (answer, ttl) = match result {
+            Some(ip) => (Answer::Found(ip), ip_ttl()),
+            None => (Answer::NotFound, self.negative_ttl),
+        };
+        self.cache.lock().await.insert(key, answer, ttl);
+        Ok(result)
+    }
+
+    async fn resolve_v4(
+        &self,
+        host: &str,
+        enhanced: bool,
+    ) -> anyhow::Result<Option<std::net::Ipv4Addr>> {
+        let key = (host.to_owned(), RecordType::A);
+        if let Some(answer) = self.cache.lock().await.get(&key) {
+            return Ok(match answer {
+                Answer::Found(IpAddr::V4(ip)) => Some(ip),
+                _ => None,
+            });
+        }
+
+        let result = self.inner.resolve_v4(host, enhanced).await?;
+        let (answer, ttl) = match result {
+            Some(ip) => (Answer::Found(IpAddr::V4(ip)), ip_ttl()),
+            None => (Answer::NotFound, self.negative_ttl),
+        };
+        self.cache.lock().await.insert(key, answer, ttl);
+        Ok(result)
+    }
+
+    async fn resolve_v6(
+        &self,
+        host: &str,
+        enhanced: bool,
+    ) -> anyhow::Result<Option<std::net::Ipv6Addr>> {
+        let key = (host.to_owned(), RecordType::Aaaa);
+        if let Some(answer) = self.cache.lock().await.get(&key) {
+            return Ok(match answer {
+                Answer::Found(IpAddr::V6(ip)) => Some(ip),
+                _ => None,
+            });
+        }
+
+        let result = self.inner.resolve_v6(host, enhanced).await?;
+        let (answer, ttl) = match result {
+            Some(ip) => (Answer::Found(IpAddr::V6(ip)), ip_ttl()),
+            None => (Answer::NotFound, self.negative_ttl),
+        };
+        self.cache.lock().await.insert(key, answer, ttl);
+        Ok(result)
+    }
+
+    fn ipv6(&self) -> bool {
+        self.inner.ipv6()
+    }
+}
+
+/// Placeholder TTL used where the resolver's answer doesn't carry record
+/// TTL through `Option<IpAddr>`. Resolvers that expose the underlying
+/// record TTL should plumb it through here instead.
+fn ip_ttl() -> Duration {
+    Duration::from_secs(60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn hit_sets_reference_and_survives_a_sweep() {
+        let mut cache = ClockProCache::new(2);
+        cache.insert("a", 1, Duration::from_secs(60));
+        cache.insert("b", 2, Duration::from_secs(60));
+
+        assert_eq!(cache.get(&"a"), Some(1));
+
+        // forces a sweep: "a" is referenced so it's promoted to hot and
+        // survives, "b" (unreferenced cold) becomes a test entry.
+        cache.insert("c", 3, Duration::from_secs(60));
+
+        assert!(cache.is_test_entry(&"b"));
+        assert_eq!(cache.get(&"c"), Some(3));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn expired_entry_is_a_miss() {
+        let mut cache = ClockProCache::new(2);
+        cache.insert("a", 1, Duration::from_millis(10));
+        tokio::time::advance(Duration::from_millis(20)).await;
+        assert_eq!(cache.get(&"a"), None);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn capacity_of_one_does_not_hang_on_repeated_insert() {
+        // regression test: a `hot_target` that reaches `capacity` leaves no
+        // slot sweep() can ever demote, so a third insert would previously
+        // spin forever once the sole resident page was promoted to hot.
+        let mut cache = ClockProCache::new(1);
+        assert_eq!(cache.hot_target, 0);
+
+        cache.insert("a", 1, Duration::from_secs(60));
+        assert_eq!(cache.get(&"a"), Some(1));
+
+        // forces a sweep with only one resident slot, which must terminate.
+        cache.insert("b", 2, Duration::from_secs(60));
+        assert_eq!(cache.get(&"b"), Some(2));
+        assert!(cache.hot_count + cache.cold_count <= 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn reinsert_after_test_enlarges_hot_target() {
+        let mut cache = ClockProCache::new(4);
+        let before = cache.hot_target;
+        cache.insert("a", 1, Duration::from_secs(60));
+        cache.demote_to_test(0);
+        cache.insert("a", 2, Duration::from_secs(60));
+        assert!(cache.hot_target > before);
+        assert_eq!(cache.get(&"a"), Some(2));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_directory_is_bounded_under_distinct_keys() {
+        let mut cache = ClockProCache::new(4);
+
+        // each key is looked up at most once, so every one it evicts from
+        // the resident set is demoted straight to a test entry and never
+        // revisited - without a bound this grows `ring`/`index` forever.
+        for i in 0..1000 {
+            cache.insert(i, i, Duration::from_secs(60));
+        }
+
+        assert!(cache.test_count <= cache.capacity);
+        assert!(cache.ring.len() <= cache.hot_count + cache.cold_count + cache.test_count);
+    }
+}
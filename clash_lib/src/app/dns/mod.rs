@@ -0,0 +1,24 @@
+//! DNS resolution: the base [`ClashResolver`] trait plus the
+//! override/cache wrapper layers built on top of it.
+
+pub mod cache;
+pub mod overrides;
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use async_trait::async_trait;
+
+/// A resolver capable of turning a hostname into an address.
+/// Implemented directly by the base resolver and transparently by the
+/// [`overrides::OverrideResolver`] / [`cache::CachedResolver`] wrapper
+/// layers, so either can be dropped in anywhere a
+/// [`ThreadSafeDNSResolver`] is expected.
+#[async_trait]
+pub trait ClashResolver: Sync + Send {
+    async fn resolve(&self, host: &str, enhanced: bool) -> anyhow::Result<Option<IpAddr>>;
+    async fn resolve_v4(&self, host: &str, enhanced: bool) -> anyhow::Result<Option<Ipv4Addr>>;
+    async fn resolve_v6(&self, host: &str, enhanced: bool) -> anyhow::Result<Option<Ipv6Addr>>;
+    fn ipv6(&self) -> bool;
+}
+
+pub type ThreadSafeDNSResolver = std::sync::Arc<dyn ClashResolver>;
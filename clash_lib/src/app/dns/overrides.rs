@@ -0,0 +1,176 @@
+//! Per-session resolver overrides: a user-supplied host -> IP table that is
+//! consulted before falling back to the normal resolver chain.
+//!
+//! This lets users pin CDN endpoints, bypass a poisoned upstream for
+//! selected domains, or force which address family is handed back for a
+//! host, without touching the global resolver configuration.
+
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::Arc,
+};
+
+use async_trait::async_trait;
+
+use crate::app::dns::{ClashResolver, ThreadSafeDNSResolver};
+
+/// A host -> IP override table, each entry loaded from config as e.g.
+/// `"*.example.com": ["1.2.3.4", "::1"]`.
+///
+/// Matching is exact first, then longest-suffix for wildcard/suffix
+/// patterns (`*.example.com` matches `a.example.com` and
+/// `b.a.example.com`, but not `example.com` itself).
+#[derive(Clone, Debug, Default)]
+pub struct HostOverrides {
+    exact: HashMap<String, Vec<IpAddr>>,
+    suffix: HashMap<String, Vec<IpAddr>>,
+}
+
+impl HostOverrides {
+    pub fn new(entries: HashMap<String, Vec<IpAddr>>) -> Self {
+        let mut exact = HashMap::new();
+        let mut suffix = HashMap::new();
+
+        for (pattern, ips) in entries {
+            match pattern.strip_prefix("*.") {
+                Some(rest) => {
+                    suffix.insert(rest.to_lowercase(), ips);
+                }
+                None => {
+                    exact.insert(pattern.to_lowercase(), ips);
+                }
+            }
+        }
+
+        Self { exact, suffix }
+    }
+
+    /// Looks up `host`, trying an exact match first and then the longest
+    /// matching wildcard suffix.
+    pub fn lookup(&self, host: &str) -> Option<&[IpAddr]> {
+        let host = host.trim_end_matches('.').to_lowercase();
+
+        if let Some(ips) = self.exact.get(&host) {
+            return Some(ips);
+        }
+
+        let mut best: Option<&str> = None;
+        for suffix in self.suffix.keys() {
+            if host.ends_with(&format!(".{suffix}"))
+                && best.map_or(true, |b| suffix.len() > b.len())
+            {
+                best = Some(suffix);
+            }
+        }
+
+        best.and_then(|s| self.suffix.get(s)).map(Vec::as_slice)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.exact.is_empty() && self.suffix.is_empty()
+    }
+}
+
+/// Wraps a [`ThreadSafeDNSResolver`] with a [`HostOverrides`] table,
+/// consulted by `resolve`/`resolve_v4`/`resolve_v6` before the wrapped
+/// resolver is asked.
+pub struct OverrideResolver {
+    overrides: HostOverrides,
+    inner: ThreadSafeDNSResolver,
+}
+
+impl OverrideResolver {
+    pub fn new(overrides: HostOverrides, inner: ThreadSafeDNSResolver) -> Arc<Self> {
+        Arc::new(Self { overrides, inner })
+    }
+}
+
+#[async_trait]
+impl ClashResolver for OverrideResolver {
+    async fn resolve(&self, host: &str, enhanced: bool) -> anyhow::Result<Option<IpAddr>> {
+        if let Some(ips) = self.overrides.lookup(host) {
+            return Ok(ips.first().copied());
+        }
+        self.inner.resolve(host, enhanced).await
+    }
+
+    async fn resolve_v4(
+        &self,
+        host: &str,
+        enhanced: bool,
+    ) -> anyhow::Result<Option<std::net::Ipv4Addr>> {
+        if let Some(ips) = self.overrides.lookup(host) {
+            return Ok(ips.iter().find_map(|ip| match ip {
+                IpAddr::V4(v4) => Some(*v4),
+                IpAddr::V6(_) => None,
+            }));
+        }
+        self.inner.resolve_v4(host, enhanced).await
+    }
+
+    async fn resolve_v6(
+        &self,
+        host: &str,
+        enhanced: bool,
+    ) -> anyhow::Result<Option<std::net::Ipv6Addr>> {
+        if let Some(ips) = self.overrides.lookup(host) {
+            return Ok(ips.iter().find_map(|ip| match ip {
+                IpAddr::V6(v6) => Some(*v6),
+                IpAddr::V4(_) => None,
+            }));
+        }
+        self.inner.resolve_v6(host, enhanced).await
+    }
+
+    fn ipv6(&self) -> bool {
+        self.inner.ipv6()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn overrides() -> HostOverrides {
+        let mut m = HashMap::new();
+        m.insert(
+            "pin.example.com".to_owned(),
+            vec!["1.1.1.1".parse().unwrap()],
+        );
+        m.insert(
+            "*.example.com".to_owned(),
+            vec!["2.2.2.2".parse().unwrap()],
+        );
+        HostOverrides::new(m)
+    }
+
+    #[test]
+    fn exact_match_wins_over_suffix() {
+        let o = overrides();
+        assert_eq!(
+            o.lookup("pin.example.com"),
+            Some(["1.1.1.1".parse().unwrap()].as_slice())
+        );
+    }
+
+    #[test]
+    fn suffix_match() {
+        let o = overrides();
+        assert_eq!(
+            o.lookup("a.example.com"),
+            Some(["2.2.2.2".parse().unwrap()].as_slice())
+        );
+        assert_eq!(
+            o.lookup("b.a.example.com"),
+            Some(["2.2.2.2".parse().unwrap()].as_slice())
+        );
+    }
+
+    #[test]
+    fn no_match() {
+        let o = overrides();
+        assert_eq!(o.lookup("example.com"), None);
+        assert_eq!(o.lookup("other.com"), None);
+    }
+}
@@ -0,0 +1,3 @@
+pub mod dns;
+
+pub use dns::ThreadSafeDNSResolver;
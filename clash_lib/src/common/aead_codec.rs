@@ -0,0 +1,250 @@
+//! Generic length-prefixed AEAD chunk codec.
+//!
+//! Both VMess (`2-byte length | AEAD(payload)`, optionally masked and
+//! padded) and shadowsocks' AEAD TCP chunks
+//! (`EncryptedLength | LengthTag | EncryptedPayload | PayloadTag`) frame
+//! their payload the same way underneath: a length prefix, an AEAD
+//! seal/open over a payload bounded by [`MAX_CHUNK_SIZE`], and the same
+//! partial-read/partial-write bookkeeping. This extracts that shared
+//! buffering and state machine so both protocols can reuse it, with the
+//! length-framing strategy (VMess's plain-or-masked 2 bytes vs.
+//! shadowsocks' separately-sealed length) left pluggable.
+//!
+//! [`AeadChunkedReader`] is split into [`AeadChunkedReader::decode_length`]
+//! and [`AeadChunkedReader::decode_payload`] rather than one combined
+//! call, since a real stream reader only learns how many more bytes to
+//! read *after* decoding the length prefix - it can't hand both halves to
+//! a single call the way a test with the whole chunk already in memory
+//! can. [`AeadChunkedReader::decode_chunk`] is the single-call convenience
+//! wrapper for the latter case.
+
+use bytes::{BufMut, BytesMut};
+
+use crate::common::utils;
+
+/// The largest chunk payload (ciphertext + overhead) either protocol will
+/// accept, matching VMess's 14-bit length field.
+pub const MAX_CHUNK_SIZE: usize = 0x3FFF;
+
+/// Abstracts an AEAD cipher's per-chunk overhead and its encrypt/decrypt
+/// in place operation, so the codec doesn't care which suite (or none, for
+/// `SECURITY_NONE`-style plaintext chunks) is in use.
+pub trait ChunkCipher {
+    /// bytes of authentication overhead added to each sealed chunk.
+    fn overhead_len(&self) -> usize;
+    fn encrypt_inplace(&mut self, buf: &mut BytesMut) -> std::io::Result<()>;
+    fn decrypt_inplace(&mut self, buf: &mut BytesMut) -> std::io::Result<()>;
+}
+
+/// Abstracts the on-wire length framing preceding each chunk.
+pub trait LengthCodec {
+    /// bytes occupied by the length field on the wire.
+    fn wire_len(&self) -> usize;
+    /// Upper bound on the padding [`Self::encode`] might append, so
+    /// [`AeadChunkedWriter::max_payload_len`] can leave room for it and
+    /// never hand back a chunk longer than [`MAX_CHUNK_SIZE`]. 0 for
+    /// codecs that don't pad.
+    fn max_padding_len(&self) -> usize {
+        0
+    }
+    /// Encodes `payload_len` (the byte length of the AEAD chunk that
+    /// follows, including its tag) into the wire length field, returning
+    /// it alongside how many bytes of random padding the caller should
+    /// append after the sealed payload (0 for codecs that don't support
+    /// padding).
+    fn encode(&mut self, payload_len: usize) -> (BytesMut, usize);
+    /// Decodes a `wire_len()`-byte length field read off the wire,
+    /// returning the on-wire chunk length that follows (sealed payload +
+    /// any trailing padding) and how many of those trailing bytes are
+    /// padding to discard rather than feed to the cipher.
+    fn decode(&mut self, bytes: &[u8]) -> std::io::Result<(usize, usize)>;
+}
+
+/// VMess-style length framing: a plain (or XOR-masked, if the caller feeds
+/// masked bytes through a custom [`LengthCodec`]) big-endian `u16`, with no
+/// padding.
+#[derive(Default)]
+pub struct PlainLength;
+
+impl LengthCodec for PlainLength {
+    fn wire_len(&self) -> usize {
+        2
+    }
+
+    fn encode(&mut self, payload_len: usize) -> (BytesMut, usize) {
+        let mut buf = BytesMut::with_capacity(2);
+        buf.put_u16(payload_len as u16);
+        (buf, 0)
+    }
+
+    fn decode(&mut self, bytes: &[u8]) -> std::io::Result<(usize, usize)> {
+        Ok((u16::from_be_bytes(bytes.try_into().unwrap()) as usize, 0))
+    }
+}
+
+/// Reader half: decodes the length prefix and sealed payload of one
+/// on-wire chunk at a time, split across [`Self::decode_length`] and
+/// [`Self::decode_payload`] so the owning stream can drive its own
+/// `poll_read` byte-accumulation the way `VmessStream` does - read
+/// `wire_len()` bytes, call `decode_length` to learn how many more to
+/// read, then `decode_payload` once they've arrived. This type holds only
+/// the cipher/length-framing state that must persist, in lock-step with
+/// the writer, across chunks (nonces, mask keystreams).
+pub struct AeadChunkedReader<C, L> {
+    cipher: Option<C>,
+    length: L,
+    pending_padding: usize,
+}
+
+impl<C: ChunkCipher, L: LengthCodec> AeadChunkedReader<C, L> {
+    pub fn new(cipher: Option<C>, length: L) -> Self {
+        Self {
+            cipher,
+            length,
+            pending_padding: 0,
+        }
+    }
+
+    pub fn wire_len(&self) -> usize {
+        self.length.wire_len()
+    }
+
+    /// Decodes a `wire_len()`-byte length field, returning how many more
+    /// bytes make up this chunk (sealed payload + any padding) - read
+    /// that many bytes, then pass them to [`Self::decode_payload`].
+    pub fn decode_length(&mut self, bytes: &[u8]) -> std::io::Result<usize> {
+        let (chunk_len, padding_len) = self.length.decode(bytes)?;
+        if chunk_len > MAX_CHUNK_SIZE {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "chunk size too large",
+            ));
+        }
+        self.pending_padding = padding_len;
+        Ok(chunk_len)
+    }
+
+    /// Decrypts in place the `chunk_len` bytes [`Self::decode_length`]
+    /// said to expect, stripping the trailing padding it reported and
+    /// returning the plaintext length.
+    pub fn decode_payload(&mut self, chunk: &mut BytesMut) -> std::io::Result<usize> {
+        let sealed_len = chunk.len() - self.pending_padding;
+        chunk.truncate(sealed_len);
+        match &mut self.cipher {
+            Some(cipher) => {
+                cipher.decrypt_inplace(chunk)?;
+                let data_len = sealed_len - cipher.overhead_len();
+                chunk.truncate(data_len);
+                Ok(data_len)
+            }
+            None => Ok(sealed_len),
+        }
+    }
+
+    /// Decodes one chunk's plaintext out of `data`, which must already
+    /// hold exactly `wire_len() + chunk_len` bytes: the length prefix
+    /// followed by the full on-wire chunk. Convenience wrapper over
+    /// [`Self::decode_length`]/[`Self::decode_payload`] for callers (tests,
+    /// datagram transports) that have the whole chunk in hand up front.
+    pub fn decode_chunk(&mut self, data: &mut BytesMut) -> std::io::Result<usize> {
+        let wire_len = self.length.wire_len();
+        let chunk_len = self.decode_length(&data[..wire_len])?;
+        let mut chunk = data.split_off(wire_len);
+        let data_len = self.decode_payload(&mut chunk)?;
+        *data = chunk;
+        Ok(data_len)
+    }
+}
+
+/// Generic writer half: encrypts a chunk of at most
+/// [`Self::max_payload_len`] plaintext bytes, prefixes it with the encoded length, and hands the
+/// assembled buffer back for the caller to flush.
+pub struct AeadChunkedWriter<C, L> {
+    cipher: Option<C>,
+    length: L,
+}
+
+impl<C: ChunkCipher, L: LengthCodec> AeadChunkedWriter<C, L> {
+    pub fn new(cipher: Option<C>, length: L) -> Self {
+        Self { cipher, length }
+    }
+
+    /// Builds one on-wire chunk (length prefix + sealed payload + any
+    /// padding the length codec asked for) out of up to
+    /// `max_payload_len()` bytes of `plaintext`, returning the buffer to
+    /// write and how many plaintext bytes it consumed.
+    pub fn encode_chunk(&mut self, plaintext: &[u8]) -> std::io::Result<(BytesMut, usize)> {
+        let overhead = self.cipher.as_ref().map_or(0, ChunkCipher::overhead_len);
+        let max_payload = self.max_payload_len();
+        let consume_len = plaintext.len().min(max_payload);
+        let payload_len = consume_len + overhead;
+
+        let mut chunk = BytesMut::with_capacity(payload_len);
+        chunk.put_slice(&plaintext[..consume_len]);
+        if let Some(cipher) = &mut self.cipher {
+            chunk.extend_from_slice(&vec![0u8; overhead]);
+            cipher.encrypt_inplace(&mut chunk)?;
+        }
+
+        let (mut out, padding_len) = self.length.encode(payload_len);
+        if padding_len > 0 {
+            let mut padding = vec![0u8; padding_len];
+            utils::rand_fill(&mut padding[..]);
+            chunk.extend_from_slice(&padding);
+        }
+        out.unsplit(chunk);
+
+        Ok((out, consume_len))
+    }
+
+    /// Largest plaintext payload that still fits in [`MAX_CHUNK_SIZE`]
+    /// once the cipher overhead and the length codec's worst-case padding
+    /// (see [`LengthCodec::max_padding_len`]) are both accounted for.
+    pub fn max_payload_len(&self) -> usize {
+        MAX_CHUNK_SIZE
+            - self.cipher.as_ref().map_or(0, ChunkCipher::overhead_len)
+            - self.length.max_padding_len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct NoopCipher;
+    impl ChunkCipher for NoopCipher {
+        fn overhead_len(&self) -> usize {
+            0
+        }
+        fn encrypt_inplace(&mut self, _buf: &mut BytesMut) -> std::io::Result<()> {
+            Ok(())
+        }
+        fn decrypt_inplace(&mut self, _buf: &mut BytesMut) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn round_trips_a_plaintext_chunk() {
+        let mut writer = AeadChunkedWriter::new(Some(NoopCipher), PlainLength);
+        let (mut wire, consumed) = writer.encode_chunk(b"hello").unwrap();
+        assert_eq!(consumed, 5);
+
+        let mut reader = AeadChunkedReader::new(Some(NoopCipher), PlainLength);
+        let n = reader.decode_chunk(&mut wire).unwrap();
+        assert_eq!(n, 5);
+        assert_eq!(&wire[..], b"hello");
+    }
+
+    #[test]
+    fn split_length_then_payload_matches_decode_chunk() {
+        let mut writer = AeadChunkedWriter::new(Some(NoopCipher), PlainLength);
+        let (wire, _) = writer.encode_chunk(b"hello").unwrap();
+
+        let mut reader = AeadChunkedReader::new(Some(NoopCipher), PlainLength);
+        let chunk_len = reader.decode_length(&wire[..2]).unwrap();
+        let mut chunk = BytesMut::from(&wire[2..2 + chunk_len]);
+        let n = reader.decode_payload(&mut chunk).unwrap();
+        assert_eq!(&chunk[..n], b"hello");
+    }
+}
@@ -0,0 +1,12 @@
+pub mod aead_codec;
+pub mod crypto;
+pub mod errors;
+pub mod utils;
+
+use tokio::io::{AsyncRead, AsyncWrite};
+
+/// Blanket bound satisfied by any duplex, thread-safe stream - the
+/// trait behind the boxed [`crate::proxy::AnyStream`] trait object.
+pub trait AsyncStream: AsyncRead + AsyncWrite + Unpin + Send + Sync {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send + Sync> AsyncStream for T {}
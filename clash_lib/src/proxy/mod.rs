@@ -0,0 +1,83 @@
+//! Outbound proxy handler trait and the options shared across handler
+//! implementations.
+
+pub mod mocks;
+pub mod shadowsocks;
+pub mod urltest;
+pub mod utils;
+pub mod vmess;
+
+use std::{collections::HashMap, io, sync::Arc};
+
+use erased_serde::Serialize;
+
+use crate::{
+    app::ThreadSafeDNSResolver,
+    session::{Session, SocksAddr},
+};
+
+use self::utils::proxy_protocol::ProxyProtocolVersion;
+
+pub type AnyStream = Box<dyn crate::common::AsyncStream>;
+pub type AnyOutboundHandler = Arc<dyn OutboundHandler>;
+pub type AnyOutboundDatagram = Box<dyn crate::session::OutboundDatagram>;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutboundType {
+    Direct,
+    Vmess,
+    Shadowsocks,
+    UrlTest,
+}
+
+/// Options shared across outbound handler implementations, independent of
+/// protocol.
+#[derive(Clone, Default)]
+pub struct CommonOption {
+    /// when set, a PROXY protocol header derived from the `Session` is
+    /// written to the upstream connection immediately after the
+    /// handshake and before any proxied bytes. See
+    /// [`crate::proxy::utils::proxy_protocol`].
+    pub proxy_protocol: Option<ProxyProtocolVersion>,
+}
+
+#[async_trait::async_trait]
+pub trait OutboundHandler: Sync + Send + Unpin {
+    /// The name of the outbound handler
+    fn name(&self) -> &str;
+
+    /// The protocol of the outbound handler
+    /// only contains Type information, do not rely on the underlying value
+    fn proto(&self) -> OutboundType;
+
+    /// The proxy remote address
+    async fn remote_addr(&self) -> Option<SocksAddr>;
+
+    /// whether the outbound handler support UDP
+    async fn support_udp(&self) -> bool;
+
+    /// connect to remote target via TCP
+    async fn connect_stream(
+        &self,
+        sess: &Session,
+        resolver: ThreadSafeDNSResolver,
+    ) -> io::Result<AnyStream>;
+
+    /// wraps a stream with outbound handler
+    async fn proxy_stream(
+        &self,
+        s: AnyStream,
+        sess: &Session,
+        resolver: ThreadSafeDNSResolver,
+    ) -> io::Result<AnyStream>;
+
+    /// connect to remote target via UDP
+    async fn connect_datagram(
+        &self,
+        sess: &Session,
+        resolver: ThreadSafeDNSResolver,
+    ) -> io::Result<AnyOutboundDatagram>;
+
+    /// for API
+    fn as_map(&self) -> HashMap<String, Box<dyn Serialize + Send>>;
+}
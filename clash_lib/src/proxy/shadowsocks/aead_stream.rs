@@ -0,0 +1,321 @@
+//! Shadowsocks AEAD stream transport, built on the shared
+//! [`crate::common::aead_codec`] chunk codec.
+//!
+//! Shadowsocks frames each TCP chunk as
+//! `EncryptedLength | LengthTag | EncryptedPayload | PayloadTag`: unlike
+//! VMess, the 2-byte length itself is sealed with its own AEAD tag rather
+//! than sent in the clear (or merely XOR-masked), and it never carries
+//! padding. [`SealedLength`] plugs that framing into the generic codec;
+//! everything else (chunk bounding, buffering) is shared with VMess's
+//! consumer of the same codec.
+//!
+//! The length and payload are sealed by two separate cipher instances,
+//! each advancing its own nonce once per chunk, so the two sides only need
+//! to agree on which instance seals which field, not on a combined nonce
+//! scheme or on the order the two fields are sealed in.
+
+use std::{
+    mem::MaybeUninit,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use bytes::{BufMut, BytesMut};
+use futures::ready;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+use crate::common::aead_codec::{AeadChunkedReader, AeadChunkedWriter, ChunkCipher, LengthCodec};
+
+/// Length framing where the 2-byte chunk length is itself sealed with its
+/// own AEAD tag, using a second [`ChunkCipher`] (same key, independent
+/// nonce sequence) dedicated to the length field.
+pub struct SealedLength<C> {
+    cipher: C,
+}
+
+impl<C: ChunkCipher> SealedLength<C> {
+    pub fn new(cipher: C) -> Self {
+        Self { cipher }
+    }
+}
+
+impl<C: ChunkCipher> LengthCodec for SealedLength<C> {
+    fn wire_len(&self) -> usize {
+        2 + self.cipher.overhead_len()
+    }
+
+    fn encode(&mut self, payload_len: usize) -> (BytesMut, usize) {
+        let mut buf = BytesMut::with_capacity(self.wire_len());
+        buf.put_u16(payload_len as u16);
+        buf.extend_from_slice(&vec![0u8; self.cipher.overhead_len()]);
+        self.cipher
+            .encrypt_inplace(&mut buf)
+            .expect("sealing a 2-byte length cannot fail");
+        (buf, 0)
+    }
+
+    fn decode(&mut self, bytes: &[u8]) -> std::io::Result<(usize, usize)> {
+        let mut buf = BytesMut::from(bytes);
+        self.cipher.decrypt_inplace(&mut buf)?;
+        Ok((u16::from_be_bytes(buf[..2].try_into().unwrap()) as usize, 0))
+    }
+}
+
+enum ReadState {
+    WaitingLength,
+    WaitingPayload(usize),
+    FlushingData(usize),
+}
+
+enum WriteState {
+    BuildingData,
+    FlushingData(usize, (usize, usize)),
+}
+
+/// A duplex shadowsocks AEAD stream: wraps an inner transport and drives
+/// [`AeadChunkedReader`]/[`AeadChunkedWriter`] over it the same way
+/// [`crate::proxy::vmess::vmess_impl::stream::VmessStream`] drives them for
+/// VMess, with `SealedLength` in place of VMess's plain/masked length.
+pub struct ShadowsocksAeadStream<S, C> {
+    stream: S,
+    reader: AeadChunkedReader<C, SealedLength<C>>,
+    writer: AeadChunkedWriter<C, SealedLength<C>>,
+
+    read_state: ReadState,
+    read_pos: usize,
+    read_buf: BytesMut,
+
+    write_state: WriteState,
+    write_buf: BytesMut,
+}
+
+impl<S, C> ShadowsocksAeadStream<S, C>
+where
+    C: ChunkCipher,
+{
+    /// `read_cipher`/`write_cipher` seal the payload; `read_length_cipher`/
+    /// `write_length_cipher` are the independent ciphers [`SealedLength`]
+    /// uses for the length field (same key as the payload cipher, a
+    /// separate nonce sequence - construct two distinct cipher instances
+    /// rather than sharing one).
+    pub fn new(
+        stream: S,
+        read_cipher: C,
+        write_cipher: C,
+        read_length_cipher: C,
+        write_length_cipher: C,
+    ) -> Self {
+        Self {
+            stream,
+            reader: AeadChunkedReader::new(Some(read_cipher), SealedLength::new(read_length_cipher)),
+            writer: AeadChunkedWriter::new(Some(write_cipher), SealedLength::new(write_length_cipher)),
+
+            read_state: ReadState::WaitingLength,
+            read_pos: 0,
+            read_buf: BytesMut::new(),
+
+            write_state: WriteState::BuildingData,
+            write_buf: BytesMut::new(),
+        }
+    }
+}
+
+impl<S: AsyncRead + Unpin, C> ShadowsocksAeadStream<S, C> {
+    // Read exactly `size` bytes into `read_buf`, starting from position 0.
+    // Mirrors `VmessStream::poll_read_exact`.
+    fn poll_read_exact(&mut self, cx: &mut Context, size: usize) -> Poll<std::io::Result<()>> {
+        self.read_buf.reserve(size);
+        unsafe { self.read_buf.set_len(size) }
+        loop {
+            if self.read_pos < size {
+                let dst = unsafe {
+                    &mut *((&mut self.read_buf[self.read_pos..size]) as *mut _
+                        as *mut [MaybeUninit<u8>])
+                };
+                let mut buf = ReadBuf::uninit(dst);
+                let ptr = buf.filled().as_ptr();
+                ready!(Pin::new(&mut self.stream).poll_read(cx, &mut buf))?;
+                assert_eq!(ptr, buf.filled().as_ptr());
+                if buf.filled().is_empty() {
+                    return Poll::Ready(Err(std::io::Error::new(
+                        std::io::ErrorKind::UnexpectedEof,
+                        "unexpected eof",
+                    )));
+                }
+                self.read_pos += buf.filled().len();
+            } else {
+                assert!(self.read_pos == size);
+                self.read_pos = 0;
+                return Poll::Ready(Ok(()));
+            }
+        }
+    }
+}
+
+impl<S, C> AsyncRead for ShadowsocksAeadStream<S, C>
+where
+    S: AsyncRead + Unpin,
+    C: ChunkCipher,
+{
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        loop {
+            match self.read_state {
+                ReadState::WaitingLength => {
+                    let this = &mut *self;
+                    let wire_len = this.reader.wire_len();
+                    ready!(this.poll_read_exact(cx, wire_len))?;
+
+                    let chunk_len = this.reader.decode_length(&this.read_buf)?;
+                    this.read_buf.clear();
+                    this.read_state = ReadState::WaitingPayload(chunk_len);
+                }
+
+                ReadState::WaitingPayload(chunk_len) => {
+                    let this = &mut *self;
+                    ready!(this.poll_read_exact(cx, chunk_len))?;
+
+                    let data_len = this.reader.decode_payload(&mut this.read_buf)?;
+                    this.read_state = ReadState::FlushingData(data_len);
+                }
+
+                ReadState::FlushingData(size) => {
+                    let to_read = std::cmp::min(buf.remaining(), size);
+                    let payload = self.read_buf.split_to(to_read);
+                    buf.put_slice(&payload);
+                    if to_read < size {
+                        self.read_state = ReadState::FlushingData(size - to_read);
+                    } else {
+                        self.read_state = ReadState::WaitingLength;
+                    }
+                    return Poll::Ready(Ok(()));
+                }
+            }
+        }
+    }
+}
+
+impl<S, C> AsyncWrite for ShadowsocksAeadStream<S, C>
+where
+    S: AsyncWrite + Unpin,
+    C: ChunkCipher,
+{
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        loop {
+            match self.write_state {
+                WriteState::BuildingData => {
+                    let this = &mut *self;
+                    let (wire, consumed) = this.writer.encode_chunk(buf)?;
+                    this.write_buf = wire;
+                    this.write_state = WriteState::FlushingData(consumed, (this.write_buf.len(), 0));
+                }
+
+                WriteState::FlushingData(consumed, (total, written)) => {
+                    let this = &mut *self;
+                    let nw = ready!(tokio_util::io::poll_write_buf(
+                        Pin::new(&mut this.stream),
+                        cx,
+                        &mut this.write_buf
+                    ))?;
+                    if nw == 0 {
+                        return Poll::Ready(Err(std::io::Error::new(
+                            std::io::ErrorKind::WriteZero,
+                            "failed to write whole data",
+                        )));
+                    }
+
+                    if written + nw >= total {
+                        this.write_state = WriteState::BuildingData;
+                        return Poll::Ready(Ok(consumed));
+                    }
+
+                    this.write_state = WriteState::FlushingData(consumed, (total, written + nw));
+                }
+            }
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        let this = self.get_mut();
+        Pin::new(&mut this.stream).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        let this = self.get_mut();
+        Pin::new(&mut this.stream).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::aead_codec::{AeadChunkedReader, AeadChunkedWriter, PlainLength};
+
+    #[derive(Clone)]
+    struct XorCipher(u8);
+    impl ChunkCipher for XorCipher {
+        fn overhead_len(&self) -> usize {
+            1
+        }
+        fn encrypt_inplace(&mut self, buf: &mut BytesMut) -> std::io::Result<()> {
+            let n = buf.len();
+            for b in &mut buf[..n - 1] {
+                *b ^= self.0;
+            }
+            let tag = self.0;
+            buf[n - 1] = tag;
+            Ok(())
+        }
+        fn decrypt_inplace(&mut self, buf: &mut BytesMut) -> std::io::Result<()> {
+            let n = buf.len();
+            if buf[n - 1] != self.0 {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    "bad tag",
+                ));
+            }
+            for b in &mut buf[..n - 1] {
+                *b ^= self.0;
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn sealed_length_round_trips() {
+        let mut length = SealedLength::new(XorCipher(0x42));
+        let (wire, padding) = length.encode(5);
+        assert_eq!(wire.len(), 3);
+        assert_eq!(padding, 0);
+        let (decoded, padding) = length.decode(&wire).unwrap();
+        assert_eq!(decoded, 5);
+        assert_eq!(padding, 0);
+    }
+
+    #[test]
+    fn chunk_round_trips_through_sealed_length() {
+        let mut writer = AeadChunkedWriter::new(Some(XorCipher(0x7)), SealedLength::new(XorCipher(0x7)));
+        let (mut wire, consumed) = writer.encode_chunk(b"shadowsocks").unwrap();
+        assert_eq!(consumed, b"shadowsocks".len());
+
+        let mut reader = AeadChunkedReader::new(Some(XorCipher(0x7)), SealedLength::new(XorCipher(0x7)));
+        let n = reader.decode_chunk(&mut wire).unwrap();
+        assert_eq!(&wire[..n], b"shadowsocks");
+    }
+
+    #[test]
+    fn plain_length_is_unaffected_by_sealed_length_addition() {
+        let mut writer = AeadChunkedWriter::new(Some(XorCipher(0x9)), PlainLength);
+        let (mut wire, _) = writer.encode_chunk(b"vmess").unwrap();
+        let mut reader = AeadChunkedReader::new(Some(XorCipher(0x9)), PlainLength);
+        let n = reader.decode_chunk(&mut wire).unwrap();
+        assert_eq!(&wire[..n], b"vmess");
+    }
+}
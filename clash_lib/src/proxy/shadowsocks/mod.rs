@@ -0,0 +1,3 @@
+//! Shadowsocks outbound protocol support.
+
+pub mod aead_stream;
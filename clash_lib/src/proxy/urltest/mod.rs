@@ -6,6 +6,7 @@ use tracing::debug;
 
 use crate::{
     app::{
+        dns::overrides::{HostOverrides, OverrideResolver},
         proxy_manager::{
             providers::proxy_provider::ThreadSafeProxyProvider, ThreadSafeProxyManager,
         },
@@ -16,8 +17,13 @@ use crate::{
 };
 
 use super::{
-    utils::provider_helper::get_proxies_from_providers, AnyOutboundDatagram, AnyOutboundHandler,
-    AnyStream, CommonOption, OutboundHandler, OutboundType,
+    utils::{
+        pool::{ConnectionPool, PooledStream, Reusable},
+        provider_helper::get_proxies_from_providers,
+        udp_health::{UdpEchoConfig, UdpHealthTracker},
+    },
+    AnyOutboundDatagram, AnyOutboundHandler, AnyStream, CommonOption, OutboundHandler,
+    OutboundType,
 };
 
 #[derive(Default)]
@@ -26,6 +32,21 @@ pub struct HandlerOptions {
     pub udp: bool,
 
     pub common_option: CommonOption,
+
+    /// when set, UDP-capable proxies are probed with a small echo payload
+    /// and a UDP-bound session prefers whichever one is currently passing.
+    pub udp_health_check: Option<UdpEchoConfig>,
+
+    /// shared idle-connection pool to dial through, if any.
+    pub pool: Option<Arc<ConnectionPool>>,
+    /// whether connections dialed through this group are safe to pool -
+    /// only true when every member the group can select is itself a
+    /// stateless, session-independent transport.
+    pub reusable: bool,
+
+    /// per-group host -> IP overrides consulted before the resolver
+    /// handed to members.
+    pub host_overrides: HostOverrides,
 }
 
 struct HandlerInner {
@@ -39,6 +60,8 @@ pub struct Handler {
     providers: Vec<ThreadSafeProxyProvider>,
     proxy_manager: ThreadSafeProxyManager,
 
+    udp_health: Option<Arc<UdpHealthTracker>>,
+
     inner: Arc<Mutex<HandlerInner>>,
 }
 
@@ -49,11 +72,17 @@ impl Handler {
         providers: Vec<ThreadSafeProxyProvider>,
         proxy_manager: ThreadSafeProxyManager,
     ) -> Self {
+        let udp_health = opts
+            .udp_health_check
+            .clone()
+            .map(|cfg| Arc::new(UdpHealthTracker::new(cfg)));
+
         Self {
             opts,
             tolerance,
             providers,
             proxy_manager,
+            udp_health,
             inner: Arc::new(Mutex::new(HandlerInner {
                 fastest_proxy: None,
             })),
@@ -64,7 +93,35 @@ impl Handler {
         get_proxies_from_providers(&self.providers, touch).await
     }
 
-    async fn fastest(&self, touch: bool) -> AnyOutboundHandler {
+    /// Delay used for ranking: the UDP probe delay when `want_udp` is set
+    /// and the proxy supports UDP and has a recorded probe, otherwise the
+    /// regular TCP/HTTP delay. A proxy that's failing its UDP probe is
+    /// ranked as maximally slow rather than excluded outright, so the group
+    /// still falls back to it if every UDP-capable proxy is down.
+    async fn delay_for(
+        &self,
+        proxy: &AnyOutboundHandler,
+        proxy_manager: &crate::app::proxy_manager::ProxyManager,
+        want_udp: bool,
+    ) -> u16 {
+        if want_udp {
+            if let Some(tracker) = &self.udp_health {
+                if proxy.support_udp().await {
+                    if let Some(result) = tracker.result(proxy.name()).await {
+                        return if result.ok {
+                            result.delay.as_millis() as u16
+                        } else {
+                            u16::MAX
+                        };
+                    }
+                }
+            }
+        }
+
+        proxy_manager.last_delay(proxy.name()).await
+    }
+
+    async fn fastest(&self, touch: bool, want_udp: bool) -> AnyOutboundHandler {
         let proxy_manager = self.proxy_manager.lock().await;
         let mut inner = self.inner.lock().await;
 
@@ -73,7 +130,7 @@ impl Handler {
             .first()
             .expect(format!("no proxy found for {}", self.name()).as_str());
 
-        let mut fastest_delay = proxy_manager.last_delay(fastest.name()).await;
+        let mut fastest_delay = self.delay_for(fastest, &proxy_manager, want_udp).await;
         let mut fast_not_exist = true;
 
         for proxy in proxies.iter().skip(1) {
@@ -87,7 +144,7 @@ impl Handler {
                 continue;
             }
 
-            let delay = proxy_manager.last_delay(proxy.name()).await;
+            let delay = self.delay_for(proxy, &proxy_manager, want_udp).await;
             if delay < fastest_delay {
                 fastest = proxy;
                 fastest_delay = delay;
@@ -114,6 +171,84 @@ impl Handler {
 
         return inner.fastest_proxy.as_ref().unwrap().clone();
     }
+
+    /// Wraps `resolver` with this group's [`HostOverrides`], if any are
+    /// configured, so every member dialed through this group sees them.
+    fn resolver_for(&self, resolver: ThreadSafeDNSResolver) -> ThreadSafeDNSResolver {
+        if self.opts.host_overrides.is_empty() {
+            resolver
+        } else {
+            OverrideResolver::new(self.opts.host_overrides.clone(), resolver)
+        }
+    }
+
+    /// Spawns a background task that re-probes every UDP-capable member on
+    /// `udp_health_check`'s configured interval, so `delay_for` already has
+    /// a result to rank on by the time a UDP-bound session asks for one
+    /// instead of ranking blind until the first session happens to trigger
+    /// a probe. A no-op returning `None` when UDP health checking isn't
+    /// configured.
+    ///
+    /// `probe_session` is reused for every probe rather than built fresh
+    /// per tick, matching how `connect_datagram` is handed a `Session` by
+    /// its caller rather than constructing one itself.
+    ///
+    /// Nothing in this tree calls this yet: whatever constructs `Handler`
+    /// from config (the outbound/group builder) needs to call this once,
+    /// right after wrapping it in the `Arc` it already keeps around, using
+    /// whatever resolver and a representative `Session` it has on hand. That
+    /// builder - and the `app::proxy_manager` module `Handler` itself
+    /// depends on - isn't present in this tree, so there's no real call
+    /// site to wire up here; until one exists, `delay_for`'s UDP branch
+    /// keeps missing `tracker.result(...)` and silently falling back to the
+    /// TCP delay.
+    pub fn spawn_udp_health_checks(
+        self: &Arc<Self>,
+        resolver: ThreadSafeDNSResolver,
+        probe_session: Session,
+    ) -> Option<tokio::task::JoinHandle<()>> {
+        let tracker = self.udp_health.clone()?;
+        let this = self.clone();
+
+        Some(tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(tracker.interval());
+            loop {
+                ticker.tick().await;
+
+                for proxy in this.get_proxies(false).await {
+                    if proxy.support_udp().await {
+                        tracker
+                            .probe(proxy.name(), &proxy, resolver.clone(), &probe_session)
+                            .await;
+                    }
+                }
+            }
+        }))
+    }
+
+    /// Dials the currently-fastest member.
+    ///
+    /// Deliberately does not touch `common_option.proxy_protocol`: by the
+    /// time `connect_stream` returns here, the selected member has already
+    /// finished its own protocol handshake (VMess, shadowsocks, ...) over
+    /// the raw connection, so anything written at this point lands as
+    /// apparent payload *inside* that tunnel rather than as a preamble the
+    /// immediate TCP/TLS peer can parse. PROXY protocol must be written by
+    /// the member's own raw dial, before its handshake begins - this group
+    /// selector just delegates to whichever member is fastest and has no
+    /// raw connection of its own to write a header onto.
+    async fn dial(&self, sess: &Session, resolver: ThreadSafeDNSResolver) -> io::Result<AnyStream> {
+        self.fastest(false, false)
+            .await
+            .connect_stream(sess, self.resolver_for(resolver))
+            .await
+    }
+}
+
+impl Reusable for Handler {
+    fn reusable(&self) -> bool {
+        self.opts.reusable
+    }
 }
 
 #[async_trait::async_trait]
@@ -130,12 +265,12 @@ impl OutboundHandler for Handler {
 
     /// The proxy remote address
     async fn remote_addr(&self) -> Option<SocksAddr> {
-        self.fastest(false).await.remote_addr().await
+        self.fastest(false, false).await.remote_addr().await
     }
 
     /// whether the outbound handler support UDP
     async fn support_udp(&self) -> bool {
-        self.opts.udp || self.fastest(false).await.support_udp().await
+        self.opts.udp || self.fastest(false, false).await.support_udp().await
     }
 
     /// connect to remote target via TCP
@@ -144,22 +279,46 @@ impl OutboundHandler for Handler {
         sess: &Session,
         resolver: ThreadSafeDNSResolver,
     ) -> io::Result<AnyStream> {
-        self.fastest(false)
-            .await
-            .connect_stream(sess, resolver)
-            .await
+        let (pool, remote) = match (&self.opts.pool, self.reusable()) {
+            (Some(pool), true) => (Some(pool.clone()), self.remote_addr().await),
+            _ => (None, None),
+        };
+
+        if let (Some(pool), Some(remote)) = (&pool, &remote) {
+            if let Some(stream) = pool.take(self.name(), remote).await {
+                return Ok(Box::new(PooledStream::new(
+                    pool.clone(),
+                    self.name().to_owned(),
+                    remote.clone(),
+                    stream,
+                )));
+            }
+        }
+
+        let stream = self.dial(sess, resolver).await?;
+
+        Ok(match (pool, remote) {
+            (Some(pool), Some(remote)) => {
+                Box::new(PooledStream::new(pool, self.name().to_owned(), remote, stream))
+            }
+            _ => stream,
+        })
     }
 
     /// wraps a stream with outbound handler
+    ///
+    /// See [`Handler::dial`]: for the same reason, this does not write a
+    /// PROXY protocol header either - `s` has already been handed to the
+    /// selected member's own handshake by the time it comes back here.
     async fn proxy_stream(
         &self,
         s: AnyStream,
         sess: &Session,
         resolver: ThreadSafeDNSResolver,
     ) -> io::Result<AnyStream> {
-        self.fastest(true)
+        self.fastest(true, sess.network == crate::session::Network::Udp)
             .await
-            .proxy_stream(s, sess, resolver)
+            .proxy_stream(s, sess, self.resolver_for(resolver))
             .await
     }
 
@@ -169,9 +328,9 @@ impl OutboundHandler for Handler {
         sess: &Session,
         resolver: ThreadSafeDNSResolver,
     ) -> io::Result<AnyOutboundDatagram> {
-        self.fastest(false)
+        self.fastest(false, true)
             .await
-            .connect_datagram(sess, resolver)
+            .connect_datagram(sess, self.resolver_for(resolver))
             .await
     }
 }
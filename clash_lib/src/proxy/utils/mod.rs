@@ -0,0 +1,4 @@
+pub mod pool;
+pub mod provider_helper;
+pub mod proxy_protocol;
+pub mod udp_health;
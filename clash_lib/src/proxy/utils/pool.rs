@@ -0,0 +1,195 @@
+//! Bounded idle-connection pool for outbound streams, keyed by
+//! `(handler name, remote address)`.
+//!
+//! This sits in front of [`OutboundHandler::connect_stream`](super::super::OutboundHandler::connect_stream)
+//! so that short-lived tunnels (e.g. many browser-driven SOCKS connections,
+//! or the health-check dials made by the `UrlTest` group) can reuse an
+//! already-established TCP+TLS connection instead of paying full handshake
+//! latency on every request.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    sync::Mutex,
+};
+
+use crate::{proxy::AnyStream, session::SocksAddr};
+
+/// Config for a [`ConnectionPool`], typically one instance shared by every
+/// handler that opts into pooling.
+#[derive(Clone, Debug)]
+pub struct PoolOptions {
+    /// max idle connections kept per `(handler, remote)` key.
+    pub max_idle_per_key: usize,
+    /// idle connections older than this are evicted instead of reused.
+    pub idle_timeout: Duration,
+}
+
+impl Default for PoolOptions {
+    fn default() -> Self {
+        Self {
+            max_idle_per_key: 4,
+            idle_timeout: Duration::from_secs(60),
+        }
+    }
+}
+
+/// Declares whether an outbound handler's streams are safe to hand back out
+/// to a different session. Stateless TCP relays are safe; protocols that
+/// bind per-connection framing/keys to a single logical session (VMess,
+/// Shadowsocks' per-connection salt, etc) are not and must keep returning
+/// `false`.
+pub trait Reusable {
+    fn reusable(&self) -> bool {
+        false
+    }
+}
+
+type Key = (String, SocksAddr);
+
+struct IdleEntry {
+    stream: AnyStream,
+    idle_since: Instant,
+}
+
+/// A bounded pool of idle upstream connections, keyed by `(handler name,
+/// remote address)`.
+pub struct ConnectionPool {
+    opts: PoolOptions,
+    idle: Mutex<HashMap<Key, VecDeque<IdleEntry>>>,
+}
+
+impl ConnectionPool {
+    pub fn new(opts: PoolOptions) -> Arc<Self> {
+        Arc::new(Self {
+            opts,
+            idle: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Takes a still-healthy idle stream for `(handler, remote)`, if any.
+    /// Stale or dead entries encountered along the way are dropped.
+    pub async fn take(&self, handler: &str, remote: &SocksAddr) -> Option<AnyStream> {
+        let key = (handler.to_owned(), remote.clone());
+        let mut idle = self.idle.lock().await;
+        let entries = idle.get_mut(&key)?;
+
+        while let Some(mut entry) = entries.pop_front() {
+            if entry.idle_since.elapsed() > self.opts.idle_timeout {
+                continue;
+            }
+            if is_dead(&mut entry.stream) {
+                continue;
+            }
+            return Some(entry.stream);
+        }
+
+        None
+    }
+
+    /// Returns a stream to the pool for future reuse. Callers must check
+    /// [`Reusable::reusable`] on the owning handler before calling this.
+    pub async fn put(&self, handler: &str, remote: &SocksAddr, stream: AnyStream) {
+        let key = (handler.to_owned(), remote.clone());
+        let mut idle = self.idle.lock().await;
+        let entries = idle.entry(key).or_default();
+
+        if entries.len() >= self.opts.max_idle_per_key {
+            // the oldest entry has had the most time to go stale - drop it
+            // rather than refuse the connection that was just freed.
+            entries.pop_front();
+        }
+
+        entries.push_back(IdleEntry {
+            stream,
+            idle_since: Instant::now(),
+        });
+    }
+}
+
+/// Wraps a stream checked out of (or freshly dialed for) a
+/// [`ConnectionPool`] so that dropping it - the caller is done with the
+/// session - hands it back for the next caller instead of closing it.
+///
+/// This only ever wraps streams from handlers that reported
+/// [`Reusable::reusable`]; nothing here re-checks that, since by the time
+/// a `PooledStream` exists the caller has already decided the wrapped
+/// connection is safe to share across sessions.
+pub struct PooledStream {
+    inner: Option<AnyStream>,
+    pool: Arc<ConnectionPool>,
+    handler: String,
+    remote: SocksAddr,
+}
+
+impl PooledStream {
+    pub fn new(pool: Arc<ConnectionPool>, handler: String, remote: SocksAddr, stream: AnyStream) -> Self {
+        Self {
+            inner: Some(stream),
+            pool,
+            handler,
+            remote,
+        }
+    }
+}
+
+impl Drop for PooledStream {
+    fn drop(&mut self) {
+        if let Some(stream) = self.inner.take() {
+            let pool = self.pool.clone();
+            let handler = std::mem::take(&mut self.handler);
+            let remote = self.remote.clone();
+            tokio::spawn(async move { pool.put(&handler, &remote, stream).await });
+        }
+    }
+}
+
+impl AsyncRead for PooledStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        Pin::new(self.get_mut().inner.as_mut().expect("polled after drop")).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for PooledStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        Pin::new(self.get_mut().inner.as_mut().expect("polled after drop")).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(self.get_mut().inner.as_mut().expect("polled after drop")).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(self.get_mut().inner.as_mut().expect("polled after drop")).poll_shutdown(cx)
+    }
+}
+
+/// Non-blocking readability probe used to weed out dead sockets before
+/// reuse: a pooled connection that's already readable is either holding
+/// unexpected bytes or was closed by the peer, neither of which is safe to
+/// hand back to a new session.
+fn is_dead(stream: &mut AnyStream) -> bool {
+    let waker = futures::task::noop_waker();
+    let mut cx = Context::from_waker(&waker);
+    let mut buf = [0u8; 1];
+    let mut read_buf = tokio::io::ReadBuf::new(&mut buf);
+    matches!(
+        Pin::new(stream).poll_read(&mut cx, &mut read_buf),
+        Poll::Ready(_)
+    )
+}
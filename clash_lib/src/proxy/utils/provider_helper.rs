@@ -0,0 +1,20 @@
+use crate::{
+    app::proxy_manager::providers::proxy_provider::ThreadSafeProxyProvider, proxy::AnyOutboundHandler,
+};
+
+/// Flattens the proxies exposed by a set of providers into a single list,
+/// optionally touching (marking recently-used) each provider first.
+pub async fn get_proxies_from_providers(
+    providers: &[ThreadSafeProxyProvider],
+    touch: bool,
+) -> Vec<AnyOutboundHandler> {
+    let mut proxies = Vec::new();
+    for provider in providers {
+        let provider = provider.lock().await;
+        if touch {
+            provider.touch().await;
+        }
+        proxies.extend(provider.proxies().await);
+    }
+    proxies
+}
@@ -0,0 +1,150 @@
+//! PROXY protocol (v1/v2) header construction.
+//!
+//! Some upstreams (load balancers, other proxies) expect the real client
+//! source/destination to be carried via a PROXY protocol header written
+//! immediately after the TCP/TLS handshake and before any proxied bytes.
+//! This is opt-in per outbound via [`crate::proxy::CommonOption::proxy_protocol`].
+
+use std::net::SocketAddr;
+
+use bytes::{BufMut, BytesMut};
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+use crate::session::Session;
+
+/// Which PROXY protocol version to emit for an outbound connection.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProxyProtocolVersion {
+    #[default]
+    V1,
+    V2,
+}
+
+const V2_SIGNATURE: [u8; 12] = [
+    0x0D, 0x0A, 0x0D, 0x0A, 0x00, 0x0D, 0x0A, 0x51, 0x55, 0x49, 0x54, 0x0A,
+];
+
+fn build_v1(src: Option<SocketAddr>, dst: Option<SocketAddr>) -> BytesMut {
+    let mut buf = BytesMut::new();
+    match (src, dst) {
+        (Some(SocketAddr::V4(s)), Some(SocketAddr::V4(d))) => buf.put_slice(
+            format!(
+                "PROXY TCP4 {} {} {} {}\r\n",
+                s.ip(),
+                d.ip(),
+                s.port(),
+                d.port()
+            )
+            .as_bytes(),
+        ),
+        (Some(SocketAddr::V6(s)), Some(SocketAddr::V6(d))) => buf.put_slice(
+            format!(
+                "PROXY TCP6 {} {} {} {}\r\n",
+                s.ip(),
+                d.ip(),
+                s.port(),
+                d.port()
+            )
+            .as_bytes(),
+        ),
+        _ => buf.put_slice(b"PROXY UNKNOWN\r\n"),
+    }
+    buf
+}
+
+fn build_v2(src: Option<SocketAddr>, dst: Option<SocketAddr>) -> BytesMut {
+    let mut buf = BytesMut::new();
+    buf.put_slice(&V2_SIGNATURE);
+    buf.put_u8(0x21); // version 2, command PROXY
+
+    match (src, dst) {
+        (Some(SocketAddr::V4(s)), Some(SocketAddr::V4(d))) => {
+            buf.put_u8(0x11); // AF_INET | STREAM
+            buf.put_u16(12); // 2 * (4-byte addr) + 2 * (2-byte port)
+            buf.put_slice(&s.ip().octets());
+            buf.put_slice(&d.ip().octets());
+            buf.put_u16(s.port());
+            buf.put_u16(d.port());
+        }
+        (Some(SocketAddr::V6(s)), Some(SocketAddr::V6(d))) => {
+            buf.put_u8(0x21); // AF_INET6 | STREAM
+            buf.put_u16(36); // 2 * (16-byte addr) + 2 * (2-byte port)
+            buf.put_slice(&s.ip().octets());
+            buf.put_slice(&d.ip().octets());
+            buf.put_u16(s.port());
+            buf.put_u16(d.port());
+        }
+        _ => {
+            // family unavailable/mismatched - AF_UNSPEC family byte with an
+            // empty address block; the command byte above stays PROXY.
+            buf.put_u8(0x00);
+            buf.put_u16(0);
+        }
+    }
+
+    buf
+}
+
+/// Writes the PROXY protocol header derived from `sess` to `stream`.
+///
+/// Must be called exactly once, immediately after the handshake and before
+/// any proxied bytes are written.
+pub async fn write_header<S>(
+    stream: &mut S,
+    version: ProxyProtocolVersion,
+    sess: &Session,
+) -> std::io::Result<()>
+where
+    S: AsyncWrite + Unpin,
+{
+    let src = sess.source;
+    let dst = sess.destination.to_socket_addr().ok();
+
+    let header = match version {
+        ProxyProtocolVersion::V1 => build_v1(src, dst),
+        ProxyProtocolVersion::V2 => build_v2(src, dst),
+    };
+
+    stream.write_all(&header).await?;
+    stream.flush().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn v1_tcp4_header() {
+        let src = "127.0.0.1:1234".parse().unwrap();
+        let dst = "10.0.0.1:443".parse().unwrap();
+        let buf = build_v1(Some(src), Some(dst));
+        assert_eq!(buf.as_ref(), b"PROXY TCP4 127.0.0.1 10.0.0.1 1234 443\r\n");
+    }
+
+    #[test]
+    fn v1_unknown_header() {
+        let buf = build_v1(None, None);
+        assert_eq!(buf.as_ref(), b"PROXY UNKNOWN\r\n");
+    }
+
+    #[test]
+    fn v2_tcp4_header() {
+        let src = "127.0.0.1:1234".parse().unwrap();
+        let dst = "10.0.0.1:443".parse().unwrap();
+        let buf = build_v2(Some(src), Some(dst));
+        assert_eq!(&buf[..12], &V2_SIGNATURE);
+        assert_eq!(buf[12], 0x21);
+        assert_eq!(buf[13], 0x11);
+        assert_eq!(u16::from_be_bytes([buf[14], buf[15]]), 12);
+    }
+
+    #[test]
+    fn v2_tcp6_header() {
+        let src = "[::1]:1234".parse().unwrap();
+        let dst = "[::2]:443".parse().unwrap();
+        let buf = build_v2(Some(src), Some(dst));
+        assert_eq!(buf[13], 0x21);
+        assert_eq!(u16::from_be_bytes([buf[14], buf[15]]), 36);
+    }
+}
@@ -0,0 +1,124 @@
+//! UDP liveness probing for UDP-capable proxies.
+//!
+//! `proxy_manager.last_delay` is measured over TCP/HTTP, so a proxy that
+//! has gone dark for UDP but still answers TCP health checks looks fine to
+//! a purely TCP-ranked group. This probes UDP-capable proxies with a small
+//! echo payload and records round-trip success/latency separately so
+//! UDP-bound sessions can be ranked on it.
+
+use std::{collections::HashMap, time::Duration};
+
+use futures::{SinkExt, StreamExt};
+use tokio::{sync::Mutex, time::Instant};
+use tracing::debug;
+
+use crate::{
+    app::ThreadSafeDNSResolver,
+    proxy::{AnyOutboundHandler, UdpPacket},
+    session::{Session, SocksAddr},
+};
+
+/// Config for the UDP echo probe.
+#[derive(Clone, Debug)]
+pub struct UdpEchoConfig {
+    /// remote echo/STUN target the probe payload is sent to.
+    pub target: SocksAddr,
+    /// how often each UDP-capable proxy is re-probed.
+    pub interval: Duration,
+    /// how long to wait for the echo before declaring the probe failed.
+    pub timeout: Duration,
+}
+
+impl Default for UdpEchoConfig {
+    fn default() -> Self {
+        Self {
+            target: SocksAddr::any_ipv4(),
+            interval: Duration::from_secs(60),
+            timeout: Duration::from_secs(3),
+        }
+    }
+}
+
+const PROBE_PAYLOAD: &[u8] = b"clash-rs-udp-probe";
+
+#[derive(Clone, Copy, Debug)]
+pub struct UdpProbeResult {
+    pub ok: bool,
+    pub delay: Duration,
+}
+
+/// Tracks the most recent UDP probe result per proxy name.
+pub struct UdpHealthTracker {
+    cfg: UdpEchoConfig,
+    results: Mutex<HashMap<String, UdpProbeResult>>,
+}
+
+impl UdpHealthTracker {
+    pub fn new(cfg: UdpEchoConfig) -> Self {
+        Self {
+            cfg,
+            results: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Last recorded probe result for `name`, if one has completed.
+    pub async fn result(&self, name: &str) -> Option<UdpProbeResult> {
+        self.results.lock().await.get(name).copied()
+    }
+
+    /// Probes `proxy` once and records the outcome under `name`.
+    pub async fn probe(
+        &self,
+        name: &str,
+        proxy: &AnyOutboundHandler,
+        resolver: ThreadSafeDNSResolver,
+        sess: &Session,
+    ) {
+        let started = Instant::now();
+        let ok = self.run_probe(proxy, resolver, sess).await;
+        let delay = started.elapsed();
+
+        if let Err(ref e) = ok {
+            debug!("udp health check for {name} failed: {e}");
+        }
+
+        self.results.lock().await.insert(
+            name.to_owned(),
+            UdpProbeResult {
+                ok: ok.is_ok(),
+                delay,
+            },
+        );
+    }
+
+    async fn run_probe(
+        &self,
+        proxy: &AnyOutboundHandler,
+        resolver: ThreadSafeDNSResolver,
+        sess: &Session,
+    ) -> std::io::Result<()> {
+        let mut datagram = proxy.connect_datagram(sess, resolver).await?;
+
+        datagram
+            .send(UdpPacket {
+                data: PROBE_PAYLOAD.to_vec(),
+                src_addr: SocksAddr::any_ipv4(),
+                dst_addr: self.cfg.target.clone(),
+            })
+            .await
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+
+        tokio::time::timeout(self.cfg.timeout, datagram.next())
+            .await
+            .map_err(|_| std::io::Error::new(std::io::ErrorKind::TimedOut, "udp probe timed out"))?
+            .ok_or_else(|| {
+                std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "udp probe stream closed")
+            })?;
+
+        Ok(())
+    }
+
+    pub fn interval(&self) -> Duration {
+        self.cfg.interval
+    }
+}
@@ -0,0 +1,3 @@
+//! VMess outbound/inbound protocol support.
+
+pub(crate) mod vmess_impl;
@@ -0,0 +1,359 @@
+//! Encoding and decoding of the VMess request header: the part of the
+//! handshake that carries the body key/iv, the security suite, and the
+//! destination address. [`seal_vmess_aead_header`] is the outbound
+//! (client) encode path; [`open_vmess_aead_header`] and
+//! [`open_vmess_legacy_header`] are the inbound (server) decode paths for
+//! the AEAD and legacy framings respectively.
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use anyhow::anyhow;
+use bytes::{BufMut, BytesMut};
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use crate::{
+    common::{crypto, utils},
+    session::SocksAddr,
+};
+
+use super::kdf;
+
+/// The fields carried by a decoded VMess request header, regardless of
+/// whether it arrived AEAD- or legacy-framed.
+pub struct DecodedRequest {
+    pub req_body_iv: Vec<u8>,
+    pub req_body_key: Vec<u8>,
+    pub resp_v: u8,
+    pub security: u8,
+    pub is_udp: bool,
+    pub dst: SocksAddr,
+    pub options: u8,
+    pub timestamp: u64,
+}
+
+const ADDR_TYPE_IPV4: u8 = 0x01;
+const ADDR_TYPE_DOMAIN: u8 = 0x02;
+const ADDR_TYPE_IPV6: u8 = 0x03;
+
+/// Parses the `[atyp, addr, port]` block `write_to_buf_vmess` produces,
+/// returning the decoded address and the number of bytes consumed.
+fn parse_dst_addr(buf: &[u8]) -> anyhow::Result<(SocksAddr, usize)> {
+    let atyp = *buf.first().ok_or_else(|| anyhow!("vmess: truncated address"))?;
+    match atyp {
+        ADDR_TYPE_IPV4 => {
+            if buf.len() < 7 {
+                return Err(anyhow!("vmess: truncated ipv4 address"));
+            }
+            let ip = Ipv4Addr::new(buf[1], buf[2], buf[3], buf[4]);
+            let port = u16::from_be_bytes([buf[5], buf[6]]);
+            Ok((SocksAddr::from((IpAddr::V4(ip), port)), 7))
+        }
+        ADDR_TYPE_IPV6 => {
+            if buf.len() < 19 {
+                return Err(anyhow!("vmess: truncated ipv6 address"));
+            }
+            let mut octets = [0u8; 16];
+            octets.copy_from_slice(&buf[1..17]);
+            let port = u16::from_be_bytes([buf[17], buf[18]]);
+            Ok((SocksAddr::from((IpAddr::V6(Ipv6Addr::from(octets)), port)), 19))
+        }
+        ADDR_TYPE_DOMAIN => {
+            if buf.len() < 2 {
+                return Err(anyhow!("vmess: truncated domain length"));
+            }
+            let len = buf[1] as usize;
+            if buf.len() < 4 + len {
+                return Err(anyhow!("vmess: truncated domain address"));
+            }
+            let domain = String::from_utf8(buf[2..2 + len].to_vec())?;
+            let port = u16::from_be_bytes([buf[2 + len], buf[3 + len]]);
+            Ok((SocksAddr::from((domain, port)), 4 + len))
+        }
+        other => Err(anyhow!("vmess: unsupported address type {other}")),
+    }
+}
+
+/// Derives the (key, iv) pair used to AES-CFB encrypt/decrypt the auth id
+/// - the sole per-user secret bound to `cmd_key`, independent of any
+/// per-connection data so it can be checked before anything else is read
+/// off the wire.
+fn auth_id_cipher_params(cmd_key: &[u8; 16]) -> ([u8; 16], [u8; 16]) {
+    let key = kdf::vmess_kdf_1_one_shot(cmd_key, kdf::KDF_SALT_CONST_AUTH_ID_ENCRYPTION_KEY);
+    let iv = kdf::vmess_kdf_1_one_shot(cmd_key, kdf::KDF_SALT_CONST_AUTH_ID_ENCRYPTION_IV);
+    let mut key16 = [0u8; 16];
+    let mut iv16 = [0u8; 16];
+    key16.copy_from_slice(&key[..16]);
+    iv16.copy_from_slice(&iv[..16]);
+    (key16, iv16)
+}
+
+/// Encodes `[timestamp(8), random(4), checksum(4)]` and AES-CFB encrypts
+/// it under a key/iv derived from `cmd_key`, producing the 16-byte auth id
+/// a client leads its handshake with.
+fn encrypt_auth_id(cmd_key: &[u8; 16], timestamp: u64) -> anyhow::Result<[u8; 16]> {
+    let (key, iv) = auth_id_cipher_params(cmd_key);
+
+    let mut plain = Vec::with_capacity(16);
+    plain.extend_from_slice(&timestamp.to_be_bytes());
+    let mut rand_bytes = [0u8; 4];
+    utils::rand_fill(&mut rand_bytes);
+    plain.extend_from_slice(&rand_bytes);
+    let sum = unsafe { boring_sys::OPENSSL_hash32(plain.as_ptr() as _, plain.len()) };
+    plain.extend_from_slice(&sum.to_be_bytes());
+
+    crypto::aes_cfb_encrypt(&key, &iv, &mut plain).map_err(|e| anyhow!("{e}"))?;
+
+    let mut out = [0u8; 16];
+    out.copy_from_slice(&plain);
+    Ok(out)
+}
+
+/// Inverse of [`encrypt_auth_id`]: decrypts `auth_id` under a key/iv
+/// derived from `cmd_key` and returns the embedded timestamp if the
+/// trailing checksum matches, or `None` if `auth_id` wasn't produced for
+/// this `cmd_key` at all (the common case: a candidate user that isn't
+/// the one the client is actually authenticating as).
+fn decrypt_auth_id(cmd_key: &[u8; 16], auth_id: &[u8; 16]) -> Option<u64> {
+    let (key, iv) = auth_id_cipher_params(cmd_key);
+
+    let mut plain = auth_id.to_vec();
+    crypto::aes_cfb_decrypt(&key, &iv, &mut plain).ok()?;
+
+    let timestamp = u64::from_be_bytes(plain[0..8].try_into().ok()?);
+    let sum = u32::from_be_bytes(plain[12..16].try_into().ok()?);
+    let expect = unsafe { boring_sys::OPENSSL_hash32(plain.as_ptr() as _, 12) };
+
+    (sum == expect).then_some(timestamp)
+}
+
+/// Builds the AEAD-framed request header a client sends after the 16-byte
+/// auth id: a length-prefixed, then payload, AEAD seal, both keyed off
+/// `cmd_key` and bound to the auth id so a sealed header can't be paired
+/// with a different auth id. Replaying the whole handshake is not prevented
+/// here; that is left to the inbound [`super::ReplayFilter`].
+pub fn seal_vmess_aead_header(
+    cmd_key: [u8; 16],
+    data: Vec<u8>,
+    timestamp: u64,
+) -> anyhow::Result<Vec<u8>> {
+    let auth_id = encrypt_auth_id(&cmd_key, timestamp)?;
+    let bound_key = [cmd_key.as_slice(), auth_id.as_slice()].concat();
+
+    let length_key =
+        &kdf::vmess_kdf_1_one_shot(&bound_key, kdf::KDF_SALT_CONST_VMESS_HEADER_PAYLOAD_LENGTH_AEAD_KEY)
+            [..16];
+    let length_iv =
+        &kdf::vmess_kdf_1_one_shot(&bound_key, kdf::KDF_SALT_CONST_VMESS_HEADER_PAYLOAD_LENGTH_AEAD_IV)
+            [..12];
+    let sealed_len = crypto::aes_gcm_seal(length_key, length_iv, &(data.len() as u16).to_be_bytes())
+        .map_err(|e| anyhow!("{e}"))?;
+
+    let payload_key =
+        &kdf::vmess_kdf_1_one_shot(&bound_key, kdf::KDF_SALT_CONST_VMESS_HEADER_PAYLOAD_AEAD_KEY)[..16];
+    let payload_iv =
+        &kdf::vmess_kdf_1_one_shot(&bound_key, kdf::KDF_SALT_CONST_VMESS_HEADER_PAYLOAD_AEAD_IV)[..12];
+    let sealed_payload =
+        crypto::aes_gcm_seal(payload_key, payload_iv, &data).map_err(|e| anyhow!("{e}"))?;
+
+    let mut out = BytesMut::with_capacity(16 + sealed_len.len() + sealed_payload.len());
+    out.put_slice(&auth_id);
+    out.put_slice(&sealed_len);
+    out.put_slice(&sealed_payload);
+    Ok(out.to_vec())
+}
+
+/// Parses the `[version, req_body_iv(16), req_body_key(16), resp_v, options,
+/// (padding_len<<4|security), reserved, command, dst, padding, checksum(4)]`
+/// layout common to both the AEAD and legacy framings.
+fn parse_decrypted_payload(buf: &[u8], timestamp: u64) -> anyhow::Result<DecodedRequest> {
+    if buf.len() < 38 {
+        return Err(anyhow!("vmess: request header too short"));
+    }
+
+    let req_body_iv = buf[1..17].to_vec();
+    let req_body_key = buf[17..33].to_vec();
+    let resp_v = buf[33];
+    let options = buf[34];
+    let padding_and_security = buf[35];
+    let padding_len = (padding_and_security >> 4) as usize;
+    let security = padding_and_security & 0x0F;
+    let command = buf[37];
+
+    let (dst, addr_len) = parse_dst_addr(&buf[38..])?;
+    let addr_end = 38 + addr_len;
+
+    if buf.len() < addr_end + padding_len + 4 {
+        return Err(anyhow!("vmess: request header truncated before checksum"));
+    }
+
+    let checksum_start = addr_end + padding_len;
+    let checksum = u32::from_be_bytes(buf[checksum_start..checksum_start + 4].try_into().unwrap());
+    let expect = unsafe { boring_sys::OPENSSL_hash32(buf.as_ptr() as _, checksum_start) };
+    if checksum != expect {
+        return Err(anyhow!("vmess: request header checksum mismatch"));
+    }
+
+    Ok(DecodedRequest {
+        req_body_iv,
+        req_body_key,
+        resp_v,
+        security,
+        is_udp: command == super::COMMAND_UDP,
+        dst,
+        options,
+        timestamp,
+    })
+}
+
+/// Opens an AEAD-framed request header. `auth_id` is checked against
+/// `cmd_key` before anything further is read off `stream`, so a
+/// non-matching candidate user leaves the stream untouched for the next
+/// candidate to try.
+pub async fn open_vmess_aead_header<S: AsyncRead + Unpin>(
+    cmd_key: [u8; 16],
+    auth_id: &[u8; 16],
+    stream: &mut S,
+) -> anyhow::Result<DecodedRequest> {
+    let timestamp =
+        decrypt_auth_id(&cmd_key, auth_id).ok_or_else(|| anyhow!("vmess: auth id mismatch"))?;
+
+    let bound_key = [cmd_key.as_slice(), auth_id.as_slice()].concat();
+
+    let length_key =
+        &kdf::vmess_kdf_1_one_shot(&bound_key, kdf::KDF_SALT_CONST_VMESS_HEADER_PAYLOAD_LENGTH_AEAD_KEY)
+            [..16];
+    let length_iv =
+        &kdf::vmess_kdf_1_one_shot(&bound_key, kdf::KDF_SALT_CONST_VMESS_HEADER_PAYLOAD_LENGTH_AEAD_IV)
+            [..12];
+
+    let mut sealed_len = [0u8; 18];
+    stream.read_exact(&mut sealed_len).await?;
+    let decrypted_len = crypto::aes_gcm_open(length_key, length_iv, &sealed_len, None)
+        .map_err(|e| anyhow!("{e}"))?;
+    if decrypted_len.len() < 2 {
+        return Err(anyhow!("vmess: request header length too short"));
+    }
+    let header_len = u16::from_be_bytes(decrypted_len[..2].try_into().unwrap()) as usize;
+
+    let payload_key =
+        &kdf::vmess_kdf_1_one_shot(&bound_key, kdf::KDF_SALT_CONST_VMESS_HEADER_PAYLOAD_AEAD_KEY)[..16];
+    let payload_iv =
+        &kdf::vmess_kdf_1_one_shot(&bound_key, kdf::KDF_SALT_CONST_VMESS_HEADER_PAYLOAD_AEAD_IV)[..12];
+
+    let mut sealed_payload = vec![0u8; header_len + 16];
+    stream.read_exact(&mut sealed_payload).await?;
+    let payload = crypto::aes_gcm_open(payload_key, payload_iv, &sealed_payload, None)
+        .map_err(|e| anyhow!("{e}"))?;
+
+    parse_decrypted_payload(&payload, timestamp)
+}
+
+/// How far ahead of / behind the server clock a legacy handshake's
+/// embedded timestamp is allowed to drift and still be accepted, mirroring
+/// the AEAD path's implicit tolerance (the AEAD auth id itself isn't
+/// timestamp-windowed at the crypto layer, but legacy framing has no
+/// equivalent binding, so the brute-force search below needs an explicit
+/// bound).
+const LEGACY_TIMESTAMP_SKEW_SECS: i64 = 120;
+
+/// Opens a legacy (non-AEAD) request header. Unlike the AEAD framing, the
+/// auth id here is just `HMAC-MD5(uuid, timestamp)` with no length
+/// prefix, so the timestamp has to be recovered by brute-forcing a small
+/// window around "now", and the header body is read incrementally off
+/// `stream` as CFB decryption reveals how long the address (and thus the
+/// whole header) turns out to be.
+pub async fn open_vmess_legacy_header<S: AsyncRead + Unpin>(
+    cmd_key: &[u8],
+    uuid_bytes: &[u8],
+    auth_id: &[u8; 16],
+    stream: &mut S,
+) -> anyhow::Result<DecodedRequest> {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::SystemTime::UNIX_EPOCH)
+        .expect("check your system clock")
+        .as_secs() as i64;
+
+    let timestamp = (-LEGACY_TIMESTAMP_SKEW_SECS..=LEGACY_TIMESTAMP_SKEW_SECS)
+        .find_map(|delta| {
+            let candidate = (now + delta) as u64;
+            let mut hash = [0u8; boring_sys::EVP_MAX_MD_SIZE as usize];
+            let mut out_len: u32 = 0;
+            unsafe {
+                boring_sys::HMAC(
+                    boring_sys::EVP_md5(),
+                    uuid_bytes.as_ptr() as _,
+                    uuid_bytes.len(),
+                    candidate.to_be_bytes().as_ptr(),
+                    8,
+                    hash.as_mut_ptr(),
+                    &mut out_len as _,
+                );
+            }
+            (hash[..out_len as usize] == auth_id[..]).then_some(candidate)
+        })
+        .ok_or_else(|| anyhow!("vmess: legacy auth id mismatch"))?;
+
+    let iv = hash_timestamp(timestamp);
+    let mut ciphertext = Vec::new();
+
+    // read through the command byte (everything up to, but not including,
+    // the address) - fixed-size regardless of what follows.
+    let decrypted = read_and_decrypt(stream, cmd_key, &iv, &mut ciphertext, 38).await?;
+    let padding_len = (decrypted[35] >> 4) as usize;
+
+    // one more byte to learn the address type.
+    let decrypted = read_and_decrypt(stream, cmd_key, &iv, &mut ciphertext, 39).await?;
+    let addr_total_len = match decrypted[38] {
+        ADDR_TYPE_IPV4 => 7,
+        ADDR_TYPE_IPV6 => 19,
+        ADDR_TYPE_DOMAIN => {
+            let decrypted = read_and_decrypt(stream, cmd_key, &iv, &mut ciphertext, 40).await?;
+            4 + decrypted[39] as usize
+        }
+        other => return Err(anyhow!("vmess: unsupported address type {other}")),
+    };
+
+    let total_len = 38 + addr_total_len + padding_len + 4;
+    let decrypted = read_and_decrypt(stream, cmd_key, &iv, &mut ciphertext, total_len).await?;
+
+    parse_decrypted_payload(&decrypted, timestamp)
+}
+
+/// Grows `ciphertext` to `target_len` bytes (reading the shortfall off
+/// `stream`) and decrypts it from scratch. Re-decrypting the whole buffer
+/// each time is wasteful but correct - CFB's self-synchronizing property
+/// means the plaintext of a ciphertext prefix never changes as more
+/// ciphertext is appended, and legacy headers are a handful of bytes.
+async fn read_and_decrypt<S: AsyncRead + Unpin>(
+    stream: &mut S,
+    cmd_key: &[u8],
+    iv: &[u8; 16],
+    ciphertext: &mut Vec<u8>,
+    target_len: usize,
+) -> anyhow::Result<Vec<u8>> {
+    if ciphertext.len() < target_len {
+        let mut more = vec![0u8; target_len - ciphertext.len()];
+        stream.read_exact(&mut more).await?;
+        ciphertext.extend_from_slice(&more);
+    }
+
+    let mut plain = ciphertext.clone();
+    crypto::aes_cfb_decrypt(cmd_key, iv, &mut plain).map_err(|e| anyhow!("{e}"))?;
+    Ok(plain)
+}
+
+fn hash_timestamp(timestamp: u64) -> [u8; 16] {
+    unsafe {
+        let mut ctx = boring_sys::MD5_CTX::default();
+        boring_sys::MD5_Init(&mut ctx);
+
+        boring_sys::MD5_Update(&mut ctx, timestamp.to_be_bytes().as_ptr() as _, 8);
+        boring_sys::MD5_Update(&mut ctx, timestamp.to_be_bytes().as_ptr() as _, 8);
+        boring_sys::MD5_Update(&mut ctx, timestamp.to_be_bytes().as_ptr() as _, 8);
+        boring_sys::MD5_Update(&mut ctx, timestamp.to_be_bytes().as_ptr() as _, 8);
+
+        let mut hash = [0u8; 16];
+        boring_sys::MD5_Final(hash.as_mut_ptr() as _, &mut ctx);
+        hash
+    }
+}
@@ -0,0 +1,48 @@
+//! VMess's key derivation function: a single-level HMAC-SHA256 keyed on a
+//! purpose-specific salt, nested under the protocol-wide `VMess AEAD KDF`
+//! salt. Every derived key/iv used on the wire - response header framing,
+//! request header framing, and the auth id itself - goes through
+//! [`vmess_kdf_1_one_shot`] with a different salt constant.
+
+const KDF_SALT_CONST_VMESS_AEAD_KDF: &[u8] = b"VMess AEAD KDF";
+
+pub const KDF_SALT_CONST_AEAD_RESP_HEADER_LEN_KEY: &[u8] = b"AEAD Resp Header Len Key";
+pub const KDF_SALT_CONST_AEAD_RESP_HEADER_LEN_IV: &[u8] = b"AEAD Resp Header Len IV";
+pub const KDF_SALT_CONST_AEAD_RESP_HEADER_PAYLOAD_KEY: &[u8] = b"AEAD Resp Header Key";
+pub const KDF_SALT_CONST_AEAD_RESP_HEADER_PAYLOAD_IV: &[u8] = b"AEAD Resp Header IV";
+
+pub const KDF_SALT_CONST_VMESS_HEADER_PAYLOAD_LENGTH_AEAD_KEY: &[u8] =
+    b"VMess Header AEAD Key_Length";
+pub const KDF_SALT_CONST_VMESS_HEADER_PAYLOAD_LENGTH_AEAD_IV: &[u8] =
+    b"VMess Header AEAD Nonce_Length";
+pub const KDF_SALT_CONST_VMESS_HEADER_PAYLOAD_AEAD_KEY: &[u8] = b"VMess Header AEAD Key";
+pub const KDF_SALT_CONST_VMESS_HEADER_PAYLOAD_AEAD_IV: &[u8] = b"VMess Header AEAD Nonce";
+
+pub const KDF_SALT_CONST_AUTH_ID_ENCRYPTION_KEY: &[u8] = b"AES Auth ID Encryption";
+pub const KDF_SALT_CONST_AUTH_ID_ENCRYPTION_IV: &[u8] = b"AES Auth ID Encryption IV";
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> [u8; 32] {
+    let mut out = [0u8; 32];
+    let mut out_len: u32 = 0;
+    unsafe {
+        boring_sys::HMAC(
+            boring_sys::EVP_sha256(),
+            key.as_ptr() as _,
+            key.len(),
+            data.as_ptr(),
+            data.len(),
+            out.as_mut_ptr(),
+            &mut out_len as _,
+        );
+    }
+    debug_assert_eq!(out_len as usize, out.len());
+    out
+}
+
+/// Derives a 32-byte key/iv material from `key` and a purpose-specific
+/// `salt`, nested under the protocol-wide KDF salt. Callers slice the
+/// result down to the key (16 bytes) or nonce (12 bytes) they need.
+pub fn vmess_kdf_1_one_shot(key: &[u8], salt: &[u8]) -> [u8; 32] {
+    let inner_key = hmac_sha256(KDF_SALT_CONST_VMESS_AEAD_KDF, salt);
+    hmac_sha256(&inner_key, key)
+}
@@ -0,0 +1,186 @@
+//! VMess's chunk-length framing, plugged into the shared
+//! [`crate::common::aead_codec`] codec: a big-endian `u16`, optionally
+//! XOR-masked by a Shake128 keystream seeded from the body IV, optionally
+//! followed by 0-63 bytes of random padding (itself sized from the same
+//! keystream) once chunk masking is negotiated.
+//!
+//! Padding only ever applies once masking does - otherwise the added
+//! length would itself be a visible signal - so [`MaskedLength`] draws the
+//! padding-length keystream value, only when padding is negotiated, right
+//! after the length mask, in the same order on both read and write, keeping
+//! the two sides' XOFs in lock-step.
+
+use bytes::{BufMut, BytesMut};
+use sha3::{
+    digest::{ExtendableOutput, Update, XofReader},
+    Shake128,
+};
+
+use crate::common::aead_codec::LengthCodec;
+
+pub type MaskXof = Box<dyn XofReader + Send + Sync>;
+
+/// Builds the (read, write) chunk-length-masking XOF keystreams for a
+/// stream, seeded from the write-side and read-side body IVs. `None` on
+/// both sides when masking wasn't negotiated.
+pub fn build_mask_xofs(
+    masking: bool,
+    write_iv: &[u8],
+    read_iv: &[u8],
+) -> (Option<MaskXof>, Option<MaskXof>) {
+    if !masking {
+        return (None, None);
+    }
+
+    let seed = |iv: &[u8]| -> MaskXof {
+        let mut hasher = Shake128::default();
+        hasher.update(iv);
+        Box::new(hasher.finalize_xof())
+    };
+
+    (Some(seed(read_iv)), Some(seed(write_iv)))
+}
+
+/// Pulls the next 2 bytes off a chunk-masking XOF keystream as a
+/// big-endian mask.
+fn next_u16(xof: &mut (dyn XofReader + Send + Sync)) -> u16 {
+    let mut buf = [0u8; 2];
+    xof.read(&mut buf);
+    u16::from_be_bytes(buf)
+}
+
+pub struct MaskedLength {
+    mask: Option<MaskXof>,
+    global_padding: bool,
+}
+
+impl MaskedLength {
+    pub fn new(mask: Option<MaskXof>, global_padding: bool) -> Self {
+        // padding only makes sense once chunk lengths are already masked -
+        // otherwise the added length would itself be a visible signal.
+        let global_padding = global_padding && mask.is_some();
+        Self { mask, global_padding }
+    }
+
+    fn padding_len(&mut self) -> usize {
+        if self.global_padding {
+            match &mut self.mask {
+                Some(xof) => (next_u16(xof.as_mut()) % 64) as usize,
+                None => 0,
+            }
+        } else {
+            0
+        }
+    }
+}
+
+impl LengthCodec for MaskedLength {
+    fn wire_len(&self) -> usize {
+        2
+    }
+
+    fn max_padding_len(&self) -> usize {
+        // `padding_len` draws `next_u16(..) % 64`, so 0-63 once global
+        // padding is negotiated, 0 otherwise.
+        if self.global_padding {
+            63
+        } else {
+            0
+        }
+    }
+
+    fn encode(&mut self, payload_len: usize) -> (BytesMut, usize) {
+        let len_mask = self.mask.as_mut().map(|xof| next_u16(xof.as_mut()));
+        let padding_len = self.padding_len();
+
+        let on_wire_len = payload_len + padding_len;
+        let masked_len = match len_mask {
+            Some(mask) => on_wire_len as u16 ^ mask,
+            None => on_wire_len as u16,
+        };
+
+        let mut buf = BytesMut::with_capacity(2);
+        buf.put_u16(masked_len);
+        (buf, padding_len)
+    }
+
+    fn decode(&mut self, bytes: &[u8]) -> std::io::Result<(usize, usize)> {
+        let masked = u16::from_be_bytes(bytes.try_into().unwrap());
+        let len = match &mut self.mask {
+            Some(xof) => masked ^ next_u16(xof.as_mut()),
+            None => masked,
+        } as usize;
+        let padding_len = self.padding_len();
+
+        Ok((len, padding_len))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unmasked_length_round_trips_with_no_padding() {
+        let mut writer = MaskedLength::new(None, true);
+        let (wire, padding) = writer.encode(10);
+        assert_eq!(padding, 0);
+        let mut reader = MaskedLength::new(None, true);
+        let (len, padding) = reader.decode(&wire).unwrap();
+        assert_eq!(len, 10);
+        assert_eq!(padding, 0);
+    }
+
+    #[test]
+    fn masked_length_round_trips_in_lock_step() {
+        let (_, write_xof) = build_mask_xofs(true, b"write-iv-deadbeef", b"read-iv-deadbeef1");
+        // the peer's read side is seeded from the IV this side writes with.
+        let (read_xof, _) = build_mask_xofs(true, b"read-iv-deadbeef1", b"write-iv-deadbeef");
+        let mut writer = MaskedLength::new(write_xof, true);
+        let (wire, padding_written) = writer.encode(20);
+
+        let mut reader = MaskedLength::new(read_xof, true);
+        let (len, padding_read) = reader.decode(&wire).unwrap();
+        assert_eq!(len, 20 + padding_written);
+        assert_eq!(padding_read, padding_written);
+    }
+
+    struct NoopCipher;
+    impl crate::common::aead_codec::ChunkCipher for NoopCipher {
+        fn overhead_len(&self) -> usize {
+            0
+        }
+        fn encrypt_inplace(&mut self, _buf: &mut BytesMut) -> std::io::Result<()> {
+            Ok(())
+        }
+        fn decrypt_inplace(&mut self, _buf: &mut BytesMut) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Regression test for a writer sizing a chunk to exactly
+    /// `max_payload_len()` with global padding negotiated: the reserved
+    /// padding budget must keep `consume_len + padding` within
+    /// `MAX_CHUNK_SIZE`, or the reader's own `decode_length` rejects the
+    /// chunk it just wrote.
+    #[test]
+    fn full_size_chunk_with_padding_never_exceeds_max_chunk_size() {
+        use crate::common::aead_codec::{AeadChunkedReader, AeadChunkedWriter, MAX_CHUNK_SIZE};
+
+        let (_, write_xof) = build_mask_xofs(true, b"write-iv-deadbeef", b"read-iv-deadbeef1");
+        // the peer's read side is seeded from the IV this side writes with.
+        let (read_xof, _) = build_mask_xofs(true, b"read-iv-deadbeef1", b"write-iv-deadbeef");
+        let mut writer =
+            AeadChunkedWriter::new(Some(NoopCipher), MaskedLength::new(write_xof, true));
+
+        let plaintext = vec![0u8; writer.max_payload_len()];
+        let (mut wire, consumed) = writer.encode_chunk(&plaintext).unwrap();
+        assert_eq!(consumed, plaintext.len());
+        assert!(wire.len() - 2 <= MAX_CHUNK_SIZE);
+
+        let mut reader =
+            AeadChunkedReader::new(Some(NoopCipher), MaskedLength::new(read_xof, true));
+        let data_len = reader.decode_chunk(&mut wire).unwrap();
+        assert_eq!(data_len, plaintext.len());
+    }
+}
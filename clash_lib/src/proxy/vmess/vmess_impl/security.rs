@@ -0,0 +1,129 @@
+//! Table-driven AEAD suite metadata for VMess.
+//!
+//! Modeled on the kind of algorithm abstraction Sequoia's OpenPGP crate
+//! exposes for `AEADAlgorithm` (`iv_size()`, `digest_size()`, a
+//! `context(key, nonce)` constructor): each supported suite reports its
+//! nonce length and tag overhead and knows how to derive its key and build
+//! a ready cipher context from a VMess body key, so [`build_cipher_context`]
+//! replaces the open-coded `match` (and its duplicated ChaCha20
+//! double-MD5 key expansion / GCM key slicing) that used to live in
+//! `stream::build_ciphers`.
+//!
+//! `SECURITY_NONE` has no suite entry: there's no cipher, no key, and no
+//! overhead to derive, so [`build_cipher_context`] reports it directly as
+//! the zero-overhead case rather than forcing a no-op `AeadSuite` impl.
+
+use aes_gcm::Aes128Gcm;
+use chacha20poly1305::ChaCha20Poly1305;
+
+use crate::common::utils;
+
+use super::{
+    cipher::VmessSecurity, Security, SECURITY_AES_128_GCM, SECURITY_CHACHA20_POLY1305,
+    SECURITY_NONE,
+};
+
+/// Per-suite metadata and key-derivation routine.
+pub trait AeadSuite: Send + Sync {
+    /// AEAD nonce length in bytes.
+    fn iv_size(&self) -> usize;
+    /// authentication tag length in bytes - the per-chunk overhead.
+    fn digest_size(&self) -> usize;
+    /// Derives the suite's key from a VMess body key and builds a cipher
+    /// context ready to hand to `AeadCipher::new`.
+    fn context(&self, body_key: &[u8]) -> VmessSecurity;
+}
+
+struct Aes128GcmSuite;
+
+impl AeadSuite for Aes128GcmSuite {
+    fn iv_size(&self) -> usize {
+        12
+    }
+
+    fn digest_size(&self) -> usize {
+        16
+    }
+
+    fn context(&self, body_key: &[u8]) -> VmessSecurity {
+        VmessSecurity::Aes128Gcm(Aes128Gcm::new_with_slice(body_key))
+    }
+}
+
+struct ChaCha20Poly1305Suite;
+
+impl AeadSuite for ChaCha20Poly1305Suite {
+    fn iv_size(&self) -> usize {
+        12
+    }
+
+    fn digest_size(&self) -> usize {
+        16
+    }
+
+    fn context(&self, body_key: &[u8]) -> VmessSecurity {
+        // VMess expands ChaCha20-Poly1305's 32-byte key from the 16-byte
+        // body key via two rounds of MD5, rather than using the body key
+        // directly as it does for AES-128-GCM.
+        let mut key = [0u8; 32];
+        let tmp = utils::md5(body_key);
+        key[..16].copy_from_slice(&tmp);
+        let tmp = utils::md5(&key[..16]);
+        key[16..].copy_from_slice(&tmp);
+
+        VmessSecurity::ChaCha20Poly1305(ChaCha20Poly1305::new_with_slice(&key))
+    }
+}
+
+/// Looks up the suite table entry for `security`, or `None` for
+/// `SECURITY_NONE` (the zero-overhead, no-cipher case) or an unsupported
+/// value.
+fn suite_for(security: &Security) -> Option<&'static dyn AeadSuite> {
+    match *security {
+        SECURITY_AES_128_GCM => Some(&Aes128GcmSuite),
+        SECURITY_CHACHA20_POLY1305 => Some(&ChaCha20Poly1305Suite),
+        _ => None,
+    }
+}
+
+/// Builds the cipher context for `security` from a VMess body key, or
+/// `None` for `SECURITY_NONE`. Returns an error for any other unsupported
+/// value.
+pub fn build_cipher_context(
+    security: &Security,
+    body_key: &[u8],
+) -> std::io::Result<Option<VmessSecurity>> {
+    if *security == SECURITY_NONE {
+        return Ok(None);
+    }
+
+    suite_for(security)
+        .map(|suite| suite.context(body_key))
+        .map(Some)
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::Other, "unsupported security"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn none_has_no_suite() {
+        assert!(build_cipher_context(&SECURITY_NONE, &[0u8; 16]).unwrap().is_none());
+    }
+
+    #[test]
+    fn aes_128_gcm_and_chacha20_both_resolve() {
+        assert!(build_cipher_context(&SECURITY_AES_128_GCM, &[0u8; 16])
+            .unwrap()
+            .is_some());
+        assert!(build_cipher_context(&SECURITY_CHACHA20_POLY1305, &[0u8; 16])
+            .unwrap()
+            .is_some());
+    }
+
+    #[test]
+    fn unsupported_security_is_an_error() {
+        assert!(build_cipher_context(&0xEE, &[0u8; 16]).is_err());
+    }
+}
@@ -1,39 +1,42 @@
-use std::{fmt::Debug, mem::MaybeUninit, pin::Pin, task::Poll, time::SystemTime};
+use std::{fmt::Debug, mem::MaybeUninit, pin::Pin, sync::Arc, task::Poll, time::SystemTime};
 
-use aes_gcm::Aes128Gcm;
-use bytes::{BufMut, BytesMut};
-use chacha20poly1305::ChaCha20Poly1305;
+use bytes::BytesMut;
 use futures::ready;
 use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt, ReadBuf};
 use tracing::debug;
 
+use tokio::io::AsyncReadExt;
+
 use crate::{
     common::{
+        aead_codec::{AeadChunkedReader, AeadChunkedWriter},
         crypto::{self, AeadCipherHelper},
         errors::map_io_error,
         utils,
     },
-    proxy::vmess::vmess_impl::MAX_CHUNK_SIZE,
     session::SocksAddr,
     vmess_debug,
 };
 
 use super::{
-    cipher::{AeadCipher, VmessSecurity},
+    cipher::AeadCipher,
     header,
+    length::{build_mask_xofs, MaskedLength},
+    replay_guard::ReplayFilter,
+    security::build_cipher_context,
     kdf::{
         self, KDF_SALT_CONST_AEAD_RESP_HEADER_LEN_IV, KDF_SALT_CONST_AEAD_RESP_HEADER_LEN_KEY,
         KDF_SALT_CONST_AEAD_RESP_HEADER_PAYLOAD_IV, KDF_SALT_CONST_AEAD_RESP_HEADER_PAYLOAD_KEY,
     },
     user::{ID, ID_BYTES_LEN},
-    Security, CHUNK_SIZE, COMMAND_TCP, COMMAND_UDP, OPTION_CHUNK_STREAM, SECURITY_AES_128_GCM,
-    SECURITY_CHACHA20_POLY1305, SECURITY_NONE, VERSION,
+    Security, COMMAND_TCP, COMMAND_UDP, OPTION_CHUNK_MASKING, OPTION_CHUNK_STREAM,
+    OPTION_GLOBAL_PADDING, VERSION,
 };
 
 pub struct VmessStream<S> {
     stream: S,
-    aead_read_cipher: Option<AeadCipher>,
-    aead_write_cipher: Option<AeadCipher>,
+    reader: AeadChunkedReader<AeadCipher, MaskedLength>,
+    writer: AeadChunkedWriter<AeadCipher, MaskedLength>,
     dst: SocksAddr,
     id: ID,
     req_body_iv: Vec<u8>,
@@ -44,6 +47,8 @@ pub struct VmessStream<S> {
     security: u8,
     is_aead: bool,
     is_udp: bool,
+    is_chunk_masking: bool,
+    is_global_padding: bool,
 
     read_state: ReadState,
     read_pos: usize,
@@ -67,6 +72,8 @@ enum ReadState {
     AeadWaitingHeaderSize,
     AeadWaitingHeader(usize),
     StreamWaitingLength,
+    /// bytes to read off the wire for this chunk (sealed payload + any
+    /// trailing padding, per `reader.decode_length`'s return value).
     StreamWaitingData(usize),
     StreamFlushingData(usize),
 }
@@ -134,6 +141,7 @@ impl<S> VmessStream<S>
 where
     S: AsyncRead + AsyncWrite + Unpin + Send + Sync,
 {
+    #[allow(clippy::too_many_arguments)]
     pub(crate) async fn new(
         stream: S,
         id: &ID,
@@ -141,6 +149,8 @@ where
         security: &Security,
         is_aead: bool,
         is_udp: bool,
+        chunk_masking: bool,
+        global_padding: bool,
     ) -> std::io::Result<VmessStream<S>> {
         let mut rand_bytes = [0u8; 33];
         utils::rand_fill(&mut rand_bytes[..]);
@@ -148,6 +158,10 @@ where
         let req_body_key = rand_bytes[16..32].to_vec();
         let resp_v = rand_bytes[32];
 
+        // padding only makes sense once chunk lengths are already masked -
+        // otherwise the added length would itself be a visible signal.
+        let global_padding = global_padding && chunk_masking;
+
         let (resp_body_key, resp_body_iv) = if is_aead {
             (
                 utils::sha256(req_body_key.as_slice())[0..16].to_vec(),
@@ -160,49 +174,21 @@ where
             )
         };
 
-        let (aead_read_cipher, aead_write_cipher) = match security {
-            &SECURITY_NONE => (None, None),
-            &SECURITY_AES_128_GCM => {
-                let write_cipher =
-                    VmessSecurity::Aes128Gcm(Aes128Gcm::new_with_slice(&req_body_key));
-                let write_cipher = AeadCipher::new(&req_body_iv, write_cipher);
-                let reader_cipher =
-                    VmessSecurity::Aes128Gcm(Aes128Gcm::new_with_slice(&resp_body_key));
-                let read_cipher = AeadCipher::new(&resp_body_iv, reader_cipher);
-                (Some(read_cipher), Some(write_cipher))
-            }
-            &SECURITY_CHACHA20_POLY1305 => {
-                let mut key = [0u8; 32];
-                let tmp = utils::md5(&req_body_key);
-                key.copy_from_slice(&tmp);
-                let tmp = utils::md5(&key[..16]);
-                key[16..].copy_from_slice(&tmp);
-                let write_cipher =
-                    VmessSecurity::ChaCha20Poly1305(ChaCha20Poly1305::new_with_slice(&key));
-                let write_cipher = AeadCipher::new(&req_body_iv, write_cipher);
-
-                let tmp = utils::md5(&req_body_key);
-                key.copy_from_slice(&tmp);
-                let tmp = utils::md5(&key[..16]);
-                key[16..].copy_from_slice(&tmp);
-                let reader_cipher =
-                    VmessSecurity::ChaCha20Poly1305(ChaCha20Poly1305::new_with_slice(&key));
-                let read_cipher = AeadCipher::new(&resp_body_iv, reader_cipher);
-
-                (Some(read_cipher), Some(write_cipher))
-            }
-            _ => {
-                return Err(std::io::Error::new(
-                    std::io::ErrorKind::Other,
-                    "unsupported security",
-                ))
-            }
-        };
+        let (aead_read_cipher, aead_write_cipher) =
+            build_ciphers(security, &req_body_key, &req_body_iv, &resp_body_key, &resp_body_iv)?;
+        let (read_mask_xof, write_mask_xof) =
+            build_mask_xofs(chunk_masking, &req_body_iv, &resp_body_iv);
 
         let mut stream = Self {
             stream,
-            aead_read_cipher,
-            aead_write_cipher,
+            reader: AeadChunkedReader::new(
+                aead_read_cipher,
+                MaskedLength::new(read_mask_xof, global_padding),
+            ),
+            writer: AeadChunkedWriter::new(
+                aead_write_cipher,
+                MaskedLength::new(write_mask_xof, global_padding),
+            ),
             dst: dst.to_owned(),
             id: id.to_owned(),
             req_body_iv,
@@ -213,6 +199,8 @@ where
             security: *security,
             is_aead,
             is_udp,
+            is_chunk_masking: chunk_masking,
+            is_global_padding: global_padding,
 
             read_state: ReadState::AeadWaitingHeaderSize,
             read_pos: 0,
@@ -226,6 +214,142 @@ where
 
         Ok(stream)
     }
+
+    /// Inbound counterpart to [`VmessStream::new`]: reads a client
+    /// handshake request off `stream`, matching it against `users`, and
+    /// returns a stream ready to relay the decoded `dst`.
+    ///
+    /// Mirrors `send_handshake_request` in reverse. Since the AEAD request
+    /// header doesn't carry the user id in the clear, each candidate in
+    /// `users` is tried in turn against [`header::open_vmess_aead_header`]
+    /// until one successfully authenticates; legacy (non-AEAD) framing is
+    /// tried the same way via [`header::open_vmess_legacy_header`].
+    ///
+    /// When `replay_filter` is set, the request's embedded timestamp and
+    /// `auth_id` are checked against it right after the header decodes, so
+    /// a stale or previously-seen handshake is rejected before any cipher
+    /// state is built.
+    pub(crate) async fn accept(
+        mut stream: S,
+        users: &[ID],
+        replay_filter: Option<&Arc<ReplayFilter>>,
+    ) -> std::io::Result<VmessStream<S>> {
+        let mut auth_id = [0u8; 16];
+        stream.read_exact(&mut auth_id).await?;
+
+        for id in users {
+            if let Ok(req) = header::open_vmess_aead_header(id.cmd_key, &auth_id, &mut stream)
+                .await
+                .map_err(map_io_error)
+            {
+                if let Some(guard) = replay_filter {
+                    guard.check(req.timestamp, auth_id).await?;
+                }
+                return Self::from_decoded_request(stream, id, true, req).await;
+            }
+        }
+
+        for id in users {
+            if let Ok(req) = header::open_vmess_legacy_header(
+                &id.cmd_key,
+                id.uuid.as_bytes(),
+                &auth_id,
+                &mut stream,
+            )
+            .await
+            .map_err(map_io_error)
+            {
+                if let Some(guard) = replay_filter {
+                    guard.check(req.timestamp, auth_id).await?;
+                }
+                return Self::from_decoded_request(stream, id, false, req).await;
+            }
+        }
+
+        Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "vmess: no user matched handshake request",
+        ))
+    }
+
+    async fn from_decoded_request(
+        stream: S,
+        id: &ID,
+        is_aead: bool,
+        req: header::DecodedRequest,
+    ) -> std::io::Result<VmessStream<S>> {
+        let header::DecodedRequest {
+            req_body_iv,
+            req_body_key,
+            resp_v,
+            security,
+            is_udp,
+            dst,
+            options,
+            timestamp: _,
+        } = req;
+
+        let chunk_masking = options & OPTION_CHUNK_MASKING != 0;
+        let global_padding = chunk_masking && options & OPTION_GLOBAL_PADDING != 0;
+
+        let (resp_body_key, resp_body_iv) = if is_aead {
+            (
+                utils::sha256(req_body_key.as_slice())[0..16].to_vec(),
+                utils::sha256(req_body_iv.as_slice())[0..16].to_vec(),
+            )
+        } else {
+            (
+                utils::md5(req_body_key.as_slice()),
+                utils::md5(req_body_iv.as_slice()),
+            )
+        };
+
+        // the server reads what the client wrote (req_*) and writes what
+        // the client expects to read back (resp_*) - the mirror image of
+        // the key assignment in `new`.
+        let (aead_write_cipher, aead_read_cipher) =
+            build_ciphers(&security, &req_body_key, &req_body_iv, &resp_body_key, &resp_body_iv)?;
+        // mirror image of `new`'s mask assignment: the server's read
+        // direction uses the request iv, its write direction the response
+        // one.
+        let (write_mask_xof, read_mask_xof) =
+            build_mask_xofs(chunk_masking, &req_body_iv, &resp_body_iv);
+
+        let mut stream = Self {
+            stream,
+            reader: AeadChunkedReader::new(
+                aead_read_cipher,
+                MaskedLength::new(read_mask_xof, global_padding),
+            ),
+            writer: AeadChunkedWriter::new(
+                aead_write_cipher,
+                MaskedLength::new(write_mask_xof, global_padding),
+            ),
+            dst,
+            id: id.to_owned(),
+            req_body_iv,
+            req_body_key,
+            resp_body_iv,
+            resp_body_key,
+            resp_v,
+            security,
+            is_aead,
+            is_udp,
+            is_chunk_masking: chunk_masking,
+            is_global_padding: global_padding,
+
+            read_state: ReadState::StreamWaitingLength,
+            read_pos: 0,
+            read_buf: BytesMut::new(),
+
+            write_state: WriteState::BuildingData,
+            write_buf: BytesMut::new(),
+        };
+
+        stream.send_handshake_response().await?;
+
+        Ok(stream)
+    }
 }
 
 impl<S> VmessStream<S>
@@ -242,6 +366,8 @@ where
             ref dst,
             ref is_aead,
             ref is_udp,
+            ref is_chunk_masking,
+            ref is_global_padding,
             ref id,
             ..
         } = self;
@@ -276,7 +402,15 @@ where
         buf.put_slice(req_body_iv);
         buf.put_slice(req_body_key);
         buf.put_u8(*resp_v);
-        buf.put_u8(OPTION_CHUNK_STREAM);
+
+        let mut options = OPTION_CHUNK_STREAM;
+        if *is_chunk_masking {
+            options |= OPTION_CHUNK_MASKING;
+        }
+        if *is_global_padding {
+            options |= OPTION_GLOBAL_PADDING;
+        }
+        buf.put_u8(options);
 
         let p = utils::rand_range(0..16);
         buf.put_u8((p << 4) as u8 | security);
@@ -323,6 +457,58 @@ where
 
         Ok(())
     }
+
+    /// Writes the handshake response expected by a client's
+    /// `AeadWaitingHeaderSize`/`AeadWaitingHeader` (or legacy) read states.
+    /// Inverse of `send_handshake_request`, using the response body
+    /// key/iv instead of the request ones.
+    async fn send_handshake_response(&mut self) -> std::io::Result<()> {
+        let Self {
+            ref mut stream,
+            ref resp_body_key,
+            ref resp_body_iv,
+            ref resp_v,
+            ref is_aead,
+            ..
+        } = self;
+
+        // [resp_v, option(0), command(0), port_len(0)]
+        let payload = [*resp_v, 0, 0, 0];
+
+        if !is_aead {
+            let mut data = payload.to_vec();
+            crypto::aes_cfb_encrypt(resp_body_key, resp_body_iv, &mut data).map_err(map_io_error)?;
+            stream.write_all(&data).await?;
+        } else {
+            let length_key = &kdf::vmess_kdf_1_one_shot(
+                resp_body_key,
+                KDF_SALT_CONST_AEAD_RESP_HEADER_LEN_KEY,
+            )[..16];
+            let length_iv = &kdf::vmess_kdf_1_one_shot(
+                resp_body_iv,
+                KDF_SALT_CONST_AEAD_RESP_HEADER_LEN_IV,
+            )[..12];
+            let sealed_len =
+                crypto::aes_gcm_seal(length_key, length_iv, &(payload.len() as u16).to_be_bytes())
+                    .map_err(map_io_error)?;
+
+            let payload_key = &kdf::vmess_kdf_1_one_shot(
+                resp_body_key,
+                KDF_SALT_CONST_AEAD_RESP_HEADER_PAYLOAD_KEY,
+            )[..16];
+            let payload_iv = &kdf::vmess_kdf_1_one_shot(
+                resp_body_iv,
+                KDF_SALT_CONST_AEAD_RESP_HEADER_PAYLOAD_IV,
+            )[..12];
+            let sealed_payload =
+                crypto::aes_gcm_seal(payload_key, payload_iv, &payload).map_err(map_io_error)?;
+
+            stream.write_all(&sealed_len).await?;
+            stream.write_all(&sealed_payload).await?;
+        }
+
+        stream.flush().await
+    }
 }
 
 impl<S> AsyncRead for VmessStream<S>
@@ -454,33 +640,18 @@ where
                 ReadState::StreamWaitingLength => {
                     vmess_debug!("recv stream length");
                     let this = &mut *self;
-                    ready!(this.poll_read_exact(cx, 2))?;
-                    let len = u16::from_be_bytes(this.read_buf.split().as_ref().try_into().unwrap())
-                        as usize;
-
-                    if len > MAX_CHUNK_SIZE {
-                        return Poll::Ready(Err(std::io::Error::new(
-                            std::io::ErrorKind::InvalidData,
-                            "invalid response - chunk size too large",
-                        )));
-                    }
-
-                    this.read_state = ReadState::StreamWaitingData(len);
+                    let wire_len = this.reader.wire_len();
+                    ready!(this.poll_read_exact(cx, wire_len))?;
+                    let chunk_len = this.reader.decode_length(&this.read_buf)?;
+                    this.read_state = ReadState::StreamWaitingData(chunk_len);
                 }
 
                 ReadState::StreamWaitingData(size) => {
                     vmess_debug!("recv stream data: {}", size);
                     let this = &mut *self;
                     ready!(this.poll_read_exact(cx, size))?;
-
-                    if let Some(ref mut cipher) = this.aead_read_cipher {
-                        cipher.decrypt_inplace(&mut this.read_buf)?;
-                        let data_len = size - cipher.security.overhead_len();
-                        this.read_buf.truncate(data_len);
-                        this.read_state = ReadState::StreamFlushingData(data_len);
-                    } else {
-                        this.read_state = ReadState::StreamFlushingData(size);
-                    }
+                    let data_len = this.reader.decode_payload(&mut this.read_buf)?;
+                    this.read_state = ReadState::StreamFlushingData(data_len);
                 }
 
                 ReadState::StreamFlushingData(size) => {
@@ -516,33 +687,11 @@ where
             match self.write_state {
                 WriteState::BuildingData => {
                     let this = &mut *self;
-                    let mut overhead_len = 0;
-                    if let Some(ref mut cipher) = this.aead_write_cipher {
-                        overhead_len = cipher.security.overhead_len();
-                    }
-
-                    let max_payload_size = CHUNK_SIZE - overhead_len;
-                    let consume_len = std::cmp::min(buf.len(), max_payload_size);
-                    let payload_len = consume_len + overhead_len;
-
-                    let size_bytes = 2;
-                    this.write_buf.reserve(size_bytes + payload_len);
-                    this.write_buf.put_u16(payload_len as u16);
-
-                    let mut piece2 = this.write_buf.split_off(size_bytes);
-
-                    piece2.put_slice(&buf[..consume_len]);
-                    if let Some(ref mut cipher) = this.aead_write_cipher {
-                        piece2
-                            .extend_from_slice(vec![0u8; cipher.security.overhead_len()].as_ref());
-                        cipher.encrypt_inplace(&mut piece2)?;
-                    }
-
-                    this.write_buf.unsplit(piece2);
+                    let (wire, consumed) = this.writer.encode_chunk(buf)?;
+                    this.write_buf = wire;
 
                     // ready to write data
-                    self.write_state =
-                        WriteState::FlushingData(consume_len, (this.write_buf.len(), 0));
+                    self.write_state = WriteState::FlushingData(consumed, (this.write_buf.len(), 0));
                 }
 
                 // consumed is the consumed plaintext length we're going to return to caller.
@@ -595,6 +744,33 @@ where
     }
 }
 
+/// Builds the (read, write) AEAD ciphers for a stream from the write-side
+/// and read-side body key/iv pairs. Shared by the client (`new`, where
+/// write = req_*, read = resp_*) and server (`from_decoded_request`, where
+/// it's the other way around) constructors.
+///
+/// Per-suite key derivation and construction is delegated to the
+/// [`super::security`] table, so this is just a pairing of (context, iv)
+/// into an `AeadCipher` for each direction. The `None` case (`SECURITY_NONE`)
+/// is handed straight to [`crate::common::aead_codec::AeadChunkedReader::new`]/
+/// [`crate::common::aead_codec::AeadChunkedWriter::new`]; the chunk methods of
+/// those two types are the only places left that branch on it - `VmessStream`
+/// itself no longer holds or matches on an `Option<AeadCipher>`.
+fn build_ciphers(
+    security: &Security,
+    write_key: &[u8],
+    write_iv: &[u8],
+    read_key: &[u8],
+    read_iv: &[u8],
+) -> std::io::Result<(Option<AeadCipher>, Option<AeadCipher>)> {
+    let write_cipher = build_cipher_context(security, write_key)?
+        .map(|context| AeadCipher::new(write_iv, context));
+    let read_cipher =
+        build_cipher_context(security, read_key)?.map(|context| AeadCipher::new(read_iv, context));
+
+    Ok((read_cipher, write_cipher))
+}
+
 fn hash_timestamp(timestamp: u64) -> [u8; 16] {
     unsafe {
         let mut ctx = boring_sys::MD5_CTX::default();
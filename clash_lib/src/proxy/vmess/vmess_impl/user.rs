@@ -0,0 +1,28 @@
+//! A VMess user identity: UUID plus the derived AEAD/legacy command key.
+
+use crate::common::utils;
+
+pub const ID_BYTES_LEN: usize = 16;
+
+/// Appended to the raw UUID bytes before hashing to derive the command
+/// key, per the VMess spec - a fixed, protocol-wide salt rather than
+/// anything secret.
+const CMD_KEY_SALT: &[u8] = b"c48619fe-8f02-49e0-b9e9-edf763e17e21";
+
+#[derive(Clone, Debug)]
+pub struct ID {
+    pub uuid: uuid::Uuid,
+    pub cmd_key: [u8; 16],
+}
+
+impl ID {
+    pub fn new(uuid: uuid::Uuid) -> Self {
+        let mut data = uuid.as_bytes().to_vec();
+        data.extend_from_slice(CMD_KEY_SALT);
+
+        let mut cmd_key = [0u8; 16];
+        cmd_key.copy_from_slice(&utils::md5(&data)[..16]);
+
+        Self { uuid, cmd_key }
+    }
+}